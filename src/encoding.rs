@@ -0,0 +1,256 @@
+use std::io;
+use std::cmp;
+use std::io::{Read, Write};
+
+use fractal::{FractalOrbit};
+use grid;
+use sampling::SamplePattern;
+use view::ViewTransform;
+
+const ORBIT_BOUNDED: u8 = 0;
+const ORBIT_ESCAPED: u8 = 1;
+
+const PATTERN_CENTER: u8 = 0;
+const PATTERN_REGULAR: u8 = 1;
+const PATTERN_JITTERED: u8 = 2;
+const PATTERN_STRATIFIED: u8 = 3;
+const PATTERN_ROTATED_GRID: u8 = 4;
+const PATTERN_HALTON: u8 = 5;
+
+// Longest string read back, far more than any parameter string or message
+// needs, so a corrupt length can't make a reader allocate without bound.
+pub const MAX_STRING_LEN: u64 = 1 << 20;
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+pub fn write_u8<W: Write>(w: &mut W, val: u8) -> io::Result<()> {
+    w.write_all(&[val])
+}
+
+pub fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn write_u64<W: Write>(w: &mut W, val: u64) -> io::Result<()> {
+    let mut buf = [0; 8];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (val >> (8*i)) as u8;
+    }
+    w.write_all(&buf)
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(buf.iter().enumerate()
+        .fold(0, |val, (i, byte)| val | ((*byte as u64) << (8*i))))
+}
+
+pub fn write_f64<W: Write>(w: &mut W, val: f64) -> io::Result<()> {
+    write_u64(w, val.to_bits())
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(r)?))
+}
+
+pub fn write_string<W: Write>(w: &mut W, val: &str) -> io::Result<()> {
+    write_u64(w, val.len() as u64)?;
+    w.write_all(val.as_bytes())
+}
+
+pub fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u64(r)?;
+    if len > MAX_STRING_LEN {
+        return Err(invalid_data("string too long"));
+    }
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| invalid_data("string is not valid UTF-8"))
+}
+
+pub fn write_orbit<W: Write>(w: &mut W, orbit: FractalOrbit) -> io::Result<()> {
+    match orbit {
+        FractalOrbit::Bounded => write_u8(w, ORBIT_BOUNDED),
+        FractalOrbit::Escaped(time) => {
+            write_u8(w, ORBIT_ESCAPED)?;
+            write_f64(w, time)
+        }
+    }
+}
+
+pub fn read_orbit<R: Read>(r: &mut R) -> io::Result<FractalOrbit> {
+    match read_u8(r)? {
+        ORBIT_BOUNDED => Ok(FractalOrbit::Bounded),
+        ORBIT_ESCAPED => Ok(FractalOrbit::Escaped(read_f64(r)?)),
+        _ => Err(invalid_data("unknown orbit tag")),
+    }
+}
+
+pub fn write_orbits<W: Write>(w: &mut W, orbits: &[FractalOrbit]) -> io::Result<()> {
+    write_u64(w, orbits.len() as u64)?;
+    for orbit in orbits.iter() {
+        write_orbit(w, *orbit)?;
+    }
+    Ok(())
+}
+
+// Reads orbits written by `write_orbits`, failing if there are more than
+// `max_len` of them.
+pub fn read_orbits<R: Read>(r: &mut R, max_len: usize) -> io::Result<Vec<FractalOrbit>> {
+    let len = read_u64(r)?;
+    if len > max_len as u64 {
+        return Err(invalid_data("too many orbits"));
+    }
+    let len = len as usize;
+    let mut orbits = Vec::with_capacity(cmp::min(len, 1 << 20));
+    for _ in 0..len {
+        orbits.push(read_orbit(r)?);
+    }
+    Ok(orbits)
+}
+
+pub fn write_grid<W: Write>(w: &mut W, grid: &grid::Grid) -> io::Result<()> {
+    let transform = grid.transform();
+    for &(x, y) in &[transform.origin, transform.column_step, transform.row_step] {
+        write_f64(w, x)?;
+        write_f64(w, y)?;
+    }
+    write_u64(w, grid.cells_wide() as u64)?;
+    write_u64(w, grid.cells_high() as u64)?;
+    let (column_offset, row_offset) = grid.cell_offset();
+    write_u64(w, column_offset as u64)?;
+    write_u64(w, row_offset as u64)?;
+    write_pattern(w, grid.sample_pattern())
+}
+
+pub fn read_grid<R: Read>(r: &mut R) -> io::Result<grid::Grid> {
+    let origin = (read_f64(r)?, read_f64(r)?);
+    let column_step = (read_f64(r)?, read_f64(r)?);
+    let row_step = (read_f64(r)?, read_f64(r)?);
+    let cells_x = read_u64(r)? as usize;
+    let cells_y = read_u64(r)? as usize;
+    let column_offset = read_u64(r)? as usize;
+    let row_offset = read_u64(r)? as usize;
+    let pattern = read_pattern(r)?;
+    if cells_x == 0 || cells_y == 0 || pattern.samples_per_cell() == 0 {
+        return Err(invalid_data("empty grid"));
+    }
+
+    let transform = ViewTransform{origin: origin, column_step: column_step,
+        row_step: row_step};
+    let mut grid = grid::Grid::from_transform(transform, cells_x, cells_y);
+    grid.set_cell_offset(column_offset, row_offset);
+    grid.set_sample_pattern(pattern);
+    Ok(grid)
+}
+
+pub fn write_pattern<W: Write>(w: &mut W, pattern: SamplePattern) -> io::Result<()> {
+    let (tag, param) = match pattern {
+        SamplePattern::Center => (PATTERN_CENTER, 0),
+        SamplePattern::Regular(n) => (PATTERN_REGULAR, n),
+        SamplePattern::Jittered(n) => (PATTERN_JITTERED, n),
+        SamplePattern::Stratified(n) => (PATTERN_STRATIFIED, n),
+        SamplePattern::RotatedGrid => (PATTERN_ROTATED_GRID, 0),
+        SamplePattern::Halton(n) => (PATTERN_HALTON, n),
+    };
+    write_u8(w, tag)?;
+    write_u64(w, param as u64)
+}
+
+pub fn read_pattern<R: Read>(r: &mut R) -> io::Result<SamplePattern> {
+    let tag = read_u8(r)?;
+    let param = read_u64(r)?;
+    if param > 1 << 16 {
+        return Err(invalid_data("sample pattern too large"));
+    }
+    let param = param as usize;
+    match tag {
+        PATTERN_CENTER => Ok(SamplePattern::Center),
+        PATTERN_REGULAR => Ok(SamplePattern::Regular(param)),
+        PATTERN_JITTERED => Ok(SamplePattern::Jittered(param)),
+        PATTERN_STRATIFIED => Ok(SamplePattern::Stratified(param)),
+        PATTERN_ROTATED_GRID => Ok(SamplePattern::RotatedGrid),
+        PATTERN_HALTON => Ok(SamplePattern::Halton(param)),
+        _ => Err(invalid_data("unknown sample pattern tag")),
+    }
+}
+
+pub fn write_strip<W: Write>(w: &mut W, strip: &grid::GridStrip) -> io::Result<()> {
+    write_u64(w, strip.start as u64)?;
+    write_u64(w, strip.height as u64)
+}
+
+pub fn write_tile<W: Write>(w: &mut W, tile: &grid::GridTile) -> io::Result<()> {
+    for val in &[tile.x, tile.y, tile.width, tile.height] {
+        write_u64(w, *val as u64)?;
+    }
+    Ok(())
+}
+
+pub fn read_tile<R: Read>(r: &mut R) -> io::Result<grid::GridTile> {
+    let x = read_u64(r)? as usize;
+    let y = read_u64(r)? as usize;
+    let width = read_u64(r)? as usize;
+    let height = read_u64(r)? as usize;
+    Ok(grid::GridTile::new(x, y, width, height))
+}
+
+pub fn read_strip<R: Read>(r: &mut R) -> io::Result<grid::GridStrip> {
+    let start = read_u64(r)? as usize;
+    let height = read_u64(r)? as usize;
+    Ok(grid::GridStrip::new(start, height))
+}
+
+pub fn checksum(data: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    data.iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ (*byte as u64)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid_data<T>(result: io::Result<T>) -> bool {
+        match result {
+            Err(e) => e.kind() == io::ErrorKind::InvalidData,
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let orbits = [FractalOrbit::Bounded, FractalOrbit::Escaped(2.5), FractalOrbit::Escaped(-0.0)];
+        let mut buf = Vec::new();
+        write_string(&mut buf, "mandelbrot iterations=100").unwrap();
+        write_orbits(&mut buf, &orbits).unwrap();
+
+        let mut r = io::Cursor::new(buf);
+        assert_eq!(read_string(&mut r).unwrap(), "mandelbrot iterations=100");
+        assert_eq!(read_orbits(&mut r, 3).unwrap(), orbits.to_vec());
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let mut buf = Vec::new();
+        write_u64(&mut buf, u64::MAX).unwrap();
+        assert!(is_invalid_data(read_string(&mut io::Cursor::new(&buf))));
+        assert!(is_invalid_data(read_orbits(&mut io::Cursor::new(&buf), 1 << 30)));
+
+        let mut buf = Vec::new();
+        write_u64(&mut buf, MAX_STRING_LEN + 1).unwrap();
+        assert!(is_invalid_data(read_string(&mut io::Cursor::new(&buf))));
+
+        let mut buf = Vec::new();
+        write_orbits(&mut buf, &[FractalOrbit::Bounded; 4]).unwrap();
+        assert!(is_invalid_data(read_orbits(&mut io::Cursor::new(&buf), 3)));
+    }
+}
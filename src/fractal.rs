@@ -9,10 +9,36 @@ pub enum FractalOrbit {
     Bounded,
 }
 
+#[derive(Copy, Debug, Clone, Default, PartialEq)]
+pub struct OrbitState {
+    pub z: Complex<f64>,
+    pub iterations: usize,
+}
+
 pub trait Fractal: Clone {
     fn test(&self, c: Complex<f64>) -> FractalOrbit;
 }
 
+pub trait IncrementalFractal: Fractal {
+    fn iteration_limit(&self) -> usize;
+    fn with_iteration_limit(&self, iter_limit: usize) -> Self;
+    fn continue_orbit(&self, c: Complex<f64>, state: &mut OrbitState) -> FractalOrbit;
+}
+
+pub trait FractalParameters {
+    fn parameters(&self) -> String;
+}
+
+pub trait FromParameters: Sized {
+    fn from_parameters(params: &str) -> Option<Self>;
+}
+
+impl OrbitState {
+    pub fn new() -> Self {
+        OrbitState{z: Complex::new(0.0, 0.0), iterations: 0}
+    }
+}
+
 #[derive(Clone)]
 pub struct FractalExecutor<T> {
     fractal_impl: T,
@@ -30,3 +56,9 @@ impl<T: Fractal> Fractal for FractalExecutor<T> {
         self.fractal_impl.test(c)
     }
 }
+
+impl<T: FractalParameters> FractalParameters for FractalExecutor<T> {
+    fn parameters(&self) -> String {
+        self.fractal_impl.parameters()
+    }
+}
@@ -1,27 +1,40 @@
+use std::cmp;
 use std::fmt;
 
-#[derive(Clone)]
+use sampling::SamplePattern;
+use view::{View, ViewTransform, UNIT_VIEW_WIDTH};
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Grid {
-    left: f64,
-    top: f64,
-    right: f64,
-    bottom: f64,
-    dx: f64,
-    dy: f64,
+    transform: ViewTransform,
     cells_x: usize,
     cells_y: usize,
+    column_offset: usize,
+    row_offset: usize,
+    sample_pattern: SamplePattern,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct GridStrip {
     pub start: usize,
     pub height: usize,
 }
 
+// A rectangle of `width` columns by `height` rows with its top left cell at
+// column `x`, row `y`. A strip is the tile spanning the full grid width.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GridTile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
 pub struct GridIter<'a> {
     grid: &'a Grid,
-    x: f64,
-    y: f64,
+    tile: GridTile,
+    cell: usize,
+    end_cell: usize,
 }
 
 pub struct StripIter<'a> {
@@ -31,19 +44,113 @@ pub struct StripIter<'a> {
 }
 
 pub struct GridStripIter<'a> {
-    grid: &'a Grid,
-    grid_strip: GridStrip,
     grid_iter: GridIter<'a>
 }
 
+pub struct TileIter<'a> {
+    grid: &'a Grid,
+    tile_width: usize,
+    tile_height: usize,
+    x: usize,
+    y: usize,
+}
+
+pub struct SampleIter<'a> {
+    grid: &'a Grid,
+    tile: GridTile,
+    cell: usize,
+    end_cell: usize,
+    current: (usize, usize),
+    positions: Vec<(f64, f64)>,
+    next_sample: usize,
+}
+
+// Iterators over a grid yield the (column, row) of each cell together with the
+// point it samples. Positions are computed from those indices rather than
+// accumulated, so every cell is visited exactly once whatever the grid size.
+pub type GridCell = ((usize, usize), (f64, f64));
+
 impl Grid {
     pub fn new(left: f64, top: f64, right: f64, bottom: f64,
                cells_x: usize, cells_y: usize) -> Self {
         let width = right-left;
         let height = top-bottom;
-        Grid{left: left, top: top, right: right, bottom: bottom,
-            dx: width / (cells_x as f64), dy: -height / (cells_y as f64),
-            cells_x: cells_x, cells_y: cells_y}
+        let transform = ViewTransform::axis_aligned((left, top),
+            width / (cells_x as f64), -height / (cells_y as f64));
+        Grid::from_transform(transform, cells_x, cells_y)
+    }
+
+    pub fn from_view(view: &View, cells_x: usize, cells_y: usize) -> Self {
+        Grid::from_transform(view.transform(cells_x, cells_y), cells_x, cells_y)
+    }
+
+    // A grid of square cells `width` wide in the complex plane, centered on
+    // `center`.
+    pub fn centered(center: (f64, f64), width: f64, cells_x: usize, cells_y: usize) -> Self {
+        Grid::from_view(&View::with_square_cells(center, width, cells_x, cells_y),
+                        cells_x, cells_y)
+    }
+
+    pub fn magnified(center: (f64, f64), magnification: f64,
+                     cells_x: usize, cells_y: usize) -> Self {
+        Grid::from_view(&View::with_magnification(center, magnification, cells_x, cells_y),
+                        cells_x, cells_y)
+    }
+
+    pub fn from_transform(transform: ViewTransform, cells_x: usize, cells_y: usize) -> Self {
+        Grid{transform: transform, cells_x: cells_x, cells_y: cells_y,
+            column_offset: 0, row_offset: 0, sample_pattern: SamplePattern::Center}
+    }
+
+    // The transform of the whole lattice; a subgrid shares its parent's
+    // transform and is placed on it by `cell_offset`.
+    #[inline]
+    pub fn transform(&self) -> &ViewTransform {
+        &self.transform
+    }
+
+    pub fn set_sample_pattern(&mut self, pattern: SamplePattern) {
+        if pattern.samples_per_cell() == 0 {
+            panic!("Sample pattern must take at least one sample per cell");
+        }
+        self.sample_pattern = pattern;
+    }
+
+    #[inline]
+    pub fn sample_pattern(&self) -> SamplePattern {
+        self.sample_pattern
+    }
+
+    #[inline]
+    pub fn samples_per_cell(&self) -> usize {
+        self.sample_pattern.samples_per_cell()
+    }
+
+    #[inline]
+    pub fn num_samples(&self) -> usize {
+        self.num_cells() * self.samples_per_cell()
+    }
+
+    #[inline]
+    pub fn num_samples_in_strip(&self, strip: &GridStrip) -> usize {
+        self.num_cells_in_strip(strip) * self.samples_per_cell()
+    }
+
+    #[inline]
+    pub fn num_samples_in_tile(&self, tile: &GridTile) -> usize {
+        tile.num_cells() * self.samples_per_cell()
+    }
+
+    // Position of this grid's first cell within the grid it was cut from, so
+    // sample patterns stay the same when a grid is split into parts.
+    pub fn set_cell_offset(&mut self, column: usize, row: usize) {
+        self.column_offset = column;
+        self.row_offset = row;
+    }
+
+    #[inline]
+    pub fn cell_offset(&self) -> (usize, usize) {
+        (self.column_offset, self.row_offset)
     }
 
     #[inline]
@@ -66,74 +173,248 @@ impl Grid {
         self.cells_y
     }
 
+    // Smallest axis aligned (left, top, right, bottom) rectangle containing
+    // the grid.
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let corners = [self.pixel_to_complex(0.0, 0.0),
+                       self.pixel_to_complex(self.cells_x as f64, 0.0),
+                       self.pixel_to_complex(0.0, self.cells_y as f64),
+                       self.pixel_to_complex(self.cells_x as f64, self.cells_y as f64)];
+        corners.iter().fold(
+            (corners[0].0, corners[0].1, corners[0].0, corners[0].1),
+            |(left, top, right, bottom), &(x, y)|
+                (left.min(x), top.max(y), right.max(x), bottom.min(y)))
+    }
+
+    #[inline]
+    pub fn center(&self) -> (f64, f64) {
+        self.pixel_to_complex((self.cells_x as f64) * 0.5, (self.cells_y as f64) * 0.5)
+    }
+
+    #[inline]
+    pub fn width(&self) -> f64 {
+        (self.cells_x as f64) * self.pixel_size().0
+    }
+
+    #[inline]
+    pub fn height(&self) -> f64 {
+        (self.cells_y as f64) * self.pixel_size().1
+    }
+
+    #[inline]
+    pub fn magnification(&self) -> f64 {
+        UNIT_VIEW_WIDTH / self.width()
+    }
+
+    // Size of a cell in the complex plane, measured along a row and across
+    // rows, so a skewed cell's height is not stretched by the skew.
+    pub fn pixel_size(&self) -> (f64, f64) {
+        let (cx, cy) = self.transform.column_step;
+        let (rx, ry) = self.transform.row_step;
+        let width = cx.hypot(cy);
+        (width, (cx * ry - cy * rx).abs() / width)
+    }
+
+    // Converts between pixel coordinates, where cell (x, y) covers
+    // [x, x+1) x [y, y+1), and points in the complex plane.
+    #[inline]
+    pub fn pixel_to_complex(&self, x: f64, y: f64) -> (f64, f64) {
+        self.transform.apply((self.column_offset as f64) + x, (self.row_offset as f64) + y)
+    }
+
+    pub fn complex_to_pixel(&self, re: f64, im: f64) -> Option<(f64, f64)> {
+        self.transform.invert(re, im)
+            .map(|(x, y)| (x - (self.column_offset as f64), y - (self.row_offset as f64)))
+    }
+
     #[inline]
     pub fn row_start(&self, row: usize) -> usize {
         if row >= self.cells_y {
             panic!("Row index out of bound");
         }
-        self.cells_y * row
+        self.cells_x * row
+    }
+
+    #[inline]
+    pub fn row_sample_start(&self, row: usize) -> usize {
+        self.row_start(row) * self.samples_per_cell()
     }
 
     #[inline]
     pub fn first_cell_position(&self) -> (f64, f64) {
-        (self.left + self.dx * 0.5, self.top + self.dy * 0.5)
+        self.pixel_to_complex(0.5, 0.5)
     }
 
     pub fn cell_position(&self, x: usize, y: usize) -> Option<(f64, f64)> {
         if x < self.cells_x && y < self.cells_y {
-            let x = self.left + ((x as f64) + 0.5) * self.dx;
-            let y = self.top + ((y as f64) + 0.5) * self.dy;
-            Some((x, y))
+            Some(self.pixel_to_complex((x as f64) + 0.5, (y as f64) + 0.5))
         } else {
             None
         }
     }
 
+    pub fn sample_positions(&self, x: usize, y: usize) -> Option<Vec<(f64, f64)>> {
+        self.sample_offsets(x, y).map(|offsets| offsets.iter()
+            .map(|&(u, v)| self.pixel_to_complex((x as f64) + u, (y as f64) + v))
+            .collect())
+    }
+
+    // Where cell (x, y)'s samples fall within it, in the units of
+    // `SamplePattern::offsets`.
+    pub fn sample_offsets(&self, x: usize, y: usize) -> Option<Vec<(f64, f64)>> {
+        if x < self.cells_x && y < self.cells_y {
+            let (column, row) = self.lattice_cell(x, y);
+            Some(self.sample_pattern.offsets(column, row))
+        } else {
+            None
+        }
+    }
+
+    // Cell (x, y)'s place on the lattice the grid's cells tile the whole
+    // plane with, counted from the cell containing 0. Any grid on the same
+    // lattice agrees on it, whether it was cut from another grid or panned
+    // by whole cells, so random sample patterns are seeded by it.
+    fn lattice_cell(&self, x: usize, y: usize) -> (i64, i64) {
+        let (origin_x, origin_y) = self.transform.invert(0.0, 0.0).unwrap_or((0.0, 0.0));
+        ((0.5 - origin_x).floor() as i64 + (x + self.column_offset) as i64,
+         (0.5 - origin_y).floor() as i64 + (y + self.row_offset) as i64)
+    }
+
+    pub fn subgrid(&self, x: usize, y: usize, cells_x: usize, cells_y: usize) -> Grid {
+        if x + cells_x > self.cells_x || y + cells_y > self.cells_y {
+            panic!("Subgrid out of bounds");
+        }
+        Grid{transform: self.transform, cells_x: cells_x, cells_y: cells_y,
+            column_offset: self.column_offset + x, row_offset: self.row_offset + y,
+            sample_pattern: self.sample_pattern}
+    }
+
+    pub fn strip_grid(&self, strip: &GridStrip) -> Grid {
+        self.tile_grid(&self.strip_tile(strip))
+    }
+
+    pub fn tile_grid(&self, tile: &GridTile) -> Grid {
+        self.subgrid(tile.x, tile.y, tile.width, tile.height)
+    }
+
+    #[inline]
+    pub fn tile(&self) -> GridTile {
+        GridTile::new(0, 0, self.cells_x, self.cells_y)
+    }
+
+    #[inline]
+    pub fn strip_tile(&self, strip: &GridStrip) -> GridTile {
+        GridTile::new(0, strip.start, self.cells_x, strip.height)
+    }
+
+    // Copies the row-major `data` computed for `tile` into its place in
+    // `values`, which holds the whole grid.
+    pub fn place_tile<T: Copy>(&self, tile: &GridTile, data: &[T], values: &mut [T]) {
+        let samples_per_cell = self.samples_per_cell();
+        let row_len = tile.width * samples_per_cell;
+        for (row, row_data) in data.chunks(row_len).enumerate() {
+            let start = self.row_sample_start(tile.y + row) + tile.x * samples_per_cell;
+            values[start..start+row_len].copy_from_slice(row_data);
+        }
+    }
+
+    // Cuts `values`, which holds the whole grid, into the disjoint parts
+    // covered by each tile of `iter_tiles(width, height)`, as one slice per
+    // row of the tile. Every tile can then be written in place concurrently.
+    pub fn split_tiles<'b, T>(&self, width: usize, height: usize, values: &'b mut [T])
+            -> Vec<(GridTile, Vec<&'b mut [T]>)> {
+        if values.len() != self.num_samples() {
+            panic!("Values don't match the grid size");
+        }
+        let mut tiles: Vec<_> = self.iter_tiles(width, height)
+            .map(|tile| (tile, Vec::with_capacity(tile.height)))
+            .collect();
+        let row_len = self.cells_x * self.samples_per_cell();
+        if row_len == 0 {
+            return tiles;
+        }
+
+        let tiles_per_band = self.cells_x.div_ceil(width);
+        for (y, mut row) in values.chunks_mut(row_len).enumerate() {
+            let band = &mut tiles[(y / height) * tiles_per_band..];
+            for &mut (ref tile, ref mut rows) in band.iter_mut().take(tiles_per_band) {
+                let (part, rest) = row.split_at_mut(tile.width * self.samples_per_cell());
+                rows.push(part);
+                row = rest;
+            }
+        }
+        tiles
+    }
+
     pub fn iter<'a>(&'a self) -> GridIter<'a> {
         GridIter::new(self)
     }
+
+    pub fn iter_samples<'a>(&'a self) -> SampleIter<'a> {
+        SampleIter::new(self, self.tile())
+    }
 }
 
 impl<'a> Grid {
     pub fn iter_strips(&'a self, height: usize) -> StripIter<'a> {
+        if height == 0 {
+            panic!("Strip height must be at least 1");
+        }
         StripIter::new(self, 0, height)
     }
+
+    // Tiles in row-major order. Tiles along the right and bottom edges are
+    // cut short where the grid doesn't divide evenly.
+    pub fn iter_tiles(&'a self, width: usize, height: usize) -> TileIter<'a> {
+        if width == 0 || height == 0 {
+            panic!("Tile size must be at least 1x1");
+        }
+        TileIter{grid: self, tile_width: width, tile_height: height, x: 0, y: 0}
+    }
 }
 
 impl<'a> GridIter<'a> {
     pub fn new(grid: &'a Grid) -> Self {
-        let first_cell = grid.first_cell_position();
-        GridIter{grid: grid, x: first_cell.0, y: first_cell.1}
+        GridIter::over_tile(grid, grid.tile())
     }
     pub fn from_row(grid: &'a Grid, row: usize) -> Self {
-        let (x, y) = grid.cell_position(0, row).unwrap();
-        GridIter{grid: grid, x: x, y: y}
+        let mut iter = GridIter::new(grid);
+        iter.cell = grid.row_start(row);
+        iter
+    }
+    pub fn over_tile(grid: &'a Grid, tile: GridTile) -> Self {
+        if tile.x + tile.width > grid.cells_x || tile.y + tile.height > grid.cells_y {
+            panic!("Tile out of bounds");
+        }
+        GridIter{grid: grid, tile: tile, cell: 0, end_cell: tile.num_cells()}
     }
 }
 
 impl<'a> Iterator for GridIter<'a> {
-    type Item = (f64, f64);
+    type Item = GridCell;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let x = self.x;
-        let y = self.y;
-
-        self.x += self.grid.dx;
-        if self.x >= self.grid.right {
-            self.x = self.grid.left + self.grid.dx * 0.5;
-            self.y += self.grid.dy;
-        }
-        if y <= self.grid.bottom {
-            None
-        } else {
-            Some((x, y))
+        if self.cell >= self.end_cell {
+            return None;
         }
+        let (x, y) = self.tile.cell(self.cell);
+        self.cell += 1;
+        self.grid.cell_position(x, y).map(|position| ((x, y), position))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end_cell.saturating_sub(self.cell);
+        (remaining, Some(remaining))
     }
 }
 
 impl fmt::Display for GridStrip {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Strip<Rows {} to {}>", self.start, self.start+self.height-1)
+        if self.height == 0 {
+            write!(f, "Strip<Empty at row {}>", self.start)
+        } else {
+            write!(f, "Strip<Rows {} to {}>", self.start, self.start+self.height-1)
+        }
     }
 }
 
@@ -148,34 +429,87 @@ impl<'a> GridStrip {
     pub fn iter(&self, grid: &'a Grid) -> GridStripIter<'a> {
         GridStripIter::new(grid, self.clone())
     }
+
+    pub fn iter_samples(&self, grid: &'a Grid) -> SampleIter<'a> {
+        SampleIter::new(grid, grid.strip_tile(self))
+    }
 }
 
 impl<'a> GridStripIter<'a> {
     pub fn new(grid: &'a Grid, strip: GridStrip) -> Self {
-        let grid_iter = GridIter::from_row(grid, strip.start);
+        let grid_iter = GridIter::over_tile(grid, grid.strip_tile(&strip));
 
-        GridStripIter{grid: grid, grid_strip: strip, grid_iter: grid_iter}
+        GridStripIter{grid_iter: grid_iter}
     }
 }
 
-impl<'a> Iterator for GridStripIter<'a> {
-    type Item = (f64, f64);
+impl fmt::Display for GridTile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.num_cells() == 0 {
+            write!(f, "Tile<Empty at column {}, row {}>", self.x, self.y)
+        } else {
+            write!(f, "Tile<Columns {} to {}, rows {} to {}>", self.x, self.x+self.width-1,
+                   self.y, self.y+self.height-1)
+        }
+    }
+}
+
+impl GridTile {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        GridTile{x: x, y: y, width: width, height: height}
+    }
+
+    #[inline]
+    pub fn num_cells(&self) -> usize {
+        self.width * self.height
+    }
+
+    // Grid coordinates of the tile's `index`th cell in row-major order.
+    #[inline]
+    pub fn cell(&self, index: usize) -> (usize, usize) {
+        (self.x + index % self.width, self.y + index / self.width)
+    }
+}
+
+impl<'a> GridTile {
+    pub fn iter(&self, grid: &'a Grid) -> GridIter<'a> {
+        GridIter::over_tile(grid, *self)
+    }
+
+    pub fn iter_samples(&self, grid: &'a Grid) -> SampleIter<'a> {
+        SampleIter::new(grid, *self)
+    }
+}
+
+impl<'a> Iterator for TileIter<'a> {
+    type Item = GridTile;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.grid_iter.next();
-        
-        match next {
-            Some((x,y)) => {
-                let end_row = self.grid_strip.start + self.grid_strip.height-1;
-                let max_y = self.grid.top + self.grid.dy*(0.5+(end_row as f64));
-                if y < max_y  {
-                    None
-                } else {
-                    Some((x,y))
-                }
-            },
-            None => None,
+        if self.x >= self.grid.cells_wide() {
+            self.x = 0;
+            self.y += self.tile_height;
         }
+        if self.y >= self.grid.cells_high() || self.grid.cells_wide() == 0 {
+            return None;
+        }
+
+        let width = cmp::min(self.tile_width, self.grid.cells_wide() - self.x);
+        let height = cmp::min(self.tile_height, self.grid.cells_high() - self.y);
+        let tile = GridTile::new(self.x, self.y, width, height);
+        self.x += self.tile_width;
+        Some(tile)
+    }
+}
+
+impl<'a> Iterator for GridStripIter<'a> {
+    type Item = GridCell;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.grid_iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.grid_iter.size_hint()
     }
 }
 
@@ -189,17 +523,8 @@ impl<'a> Iterator for StripIter<'a> {
     type Item = GridStrip;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_pos = 
-            if self.cur_pos + self.strip_height >= self.grid.cells_high() {
-                if self.cur_pos+1 == self.grid.cells_high() {
-                    self.grid.cells_high()
-                } else {
-                    self.grid.cells_high()-1
-                }
-            } else {
-                self.cur_pos + self.strip_height
-            };
-        
+        let next_pos = cmp::min(self.cur_pos + self.strip_height, self.grid.cells_high());
+
         if self.cur_pos >= self.grid.cells_high() {
             None
         } else {
@@ -210,3 +535,229 @@ impl<'a> Iterator for StripIter<'a> {
         }
     }
 }
+
+impl<'a> SampleIter<'a> {
+    pub fn new(grid: &'a Grid, tile: GridTile) -> Self {
+        if tile.x + tile.width > grid.cells_x || tile.y + tile.height > grid.cells_y {
+            panic!("Tile out of bounds");
+        }
+        SampleIter{grid: grid, tile: tile, cell: 0, end_cell: tile.num_cells(),
+            current: (0, 0), positions: Vec::new(), next_sample: 0}
+    }
+}
+
+impl<'a> Iterator for SampleIter<'a> {
+    type Item = GridCell;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_sample == self.positions.len() {
+            if self.cell >= self.end_cell {
+                return None;
+            }
+            let (x, y) = self.tile.cell(self.cell);
+            self.positions = self.grid.sample_positions(x, y).unwrap();
+            self.current = (x, y);
+            self.next_sample = 0;
+            self.cell += 1;
+        }
+
+        let position = self.positions[self.next_sample];
+        self.next_sample += 1;
+        Some((self.current, position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grids() -> Vec<Grid> {
+        vec![Grid::new(-2.0, 1.0, 1.0, -1.0, 7, 3),
+             Grid::new(-2.0, 1.0, 1.0, -1.0, 3, 7),
+             Grid::new(-1.5, 1.0, 1.0, -1.0, 1, 1),
+             Grid::new(-0.743, 0.131, -0.742, 0.130, 333, 17),
+             Grid::centered((-0.25, 0.0), 2.5, 101, 99)]
+    }
+
+    #[test]
+    fn iter_visits_every_cell_once_in_order() {
+        for grid in grids() {
+            let cells: Vec<_> = grid.iter().collect();
+            assert_eq!(cells.len(), grid.num_cells());
+            for (i, &((x, y), position)) in cells.iter().enumerate() {
+                assert_eq!((x, y), (i % grid.cells_wide(), i / grid.cells_wide()));
+                assert_eq!(Some(position), grid.cell_position(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn row_start_matches_iteration() {
+        for grid in grids() {
+            let cells: Vec<_> = grid.iter().collect();
+            for row in 0..grid.cells_high() {
+                assert_eq!((cells[grid.row_start(row)].0).1, row);
+                assert_eq!((cells[grid.row_start(row)].0).0, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn strips_cover_grid_at_row_offsets() {
+        for grid in grids() {
+            let cells: Vec<_> = grid.iter().collect();
+            for height in 1..grid.cells_high() + 2 {
+                let mut next_row = 0;
+                for strip in grid.iter_strips(height) {
+                    assert_eq!(strip.start, next_row);
+                    assert!(strip.height >= 1 && strip.height <= height);
+                    next_row += strip.height;
+
+                    let start = grid.row_start(strip.start);
+                    let strip_cells: Vec<_> = strip.iter(&grid).collect();
+                    assert_eq!(strip_cells.len(), grid.num_cells_in_strip(&strip));
+                    assert_eq!(&cells[start..start + strip_cells.len()], &strip_cells[..]);
+                }
+                assert_eq!(next_row, grid.cells_high());
+            }
+        }
+    }
+
+    #[test]
+    fn subgrid_positions_match_parent() {
+        for grid in grids() {
+            let x = grid.cells_wide() / 3;
+            let y = grid.cells_high() / 2;
+            let sub = grid.subgrid(x, y, grid.cells_wide() - x, grid.cells_high() - y);
+            for ((sx, sy), position) in sub.iter() {
+                assert_eq!(grid.cell_position(x + sx, y + sy), Some(position));
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_grouped_by_cell() {
+        for mut grid in grids() {
+            grid.set_sample_pattern(SamplePattern::Stratified(2));
+            let samples: Vec<_> = grid.iter_samples().collect();
+            assert_eq!(samples.len(), grid.num_samples());
+            for (i, &(cell, _)) in samples.iter().enumerate() {
+                let index = i / grid.samples_per_cell();
+                assert_eq!(cell, (index % grid.cells_wide(), index / grid.cells_wide()));
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_cover_grid_once() {
+        for grid in grids() {
+            let cells: Vec<_> = grid.iter().collect();
+            for &(width, height) in &[(1, 1), (2, 3), (4, 4), (64, 64), (1000, 1)] {
+                let mut placed = vec![None; grid.num_cells()];
+                for tile in grid.iter_tiles(width, height) {
+                    assert!(tile.width >= 1 && tile.width <= width);
+                    assert!(tile.height >= 1 && tile.height <= height);
+                    let data: Vec<_> = tile.iter(&grid).map(Some).collect();
+                    assert_eq!(data.len(), tile.num_cells());
+                    assert!(data.iter().all(|cell| {
+                        let ((x, y), _) = cell.unwrap();
+                        placed[grid.row_start(y) + x].is_none()
+                    }));
+                    grid.place_tile(&tile, &data, &mut placed);
+                }
+                let placed: Vec<_> = placed.into_iter().map(Option::unwrap).collect();
+                assert_eq!(placed, cells);
+            }
+        }
+    }
+
+    #[test]
+    fn split_tiles_match_placement() {
+        for mut grid in grids() {
+            grid.set_sample_pattern(SamplePattern::Regular(2));
+            let samples: Vec<_> = grid.iter_samples().collect();
+            for &(width, height) in &[(1, 1), (2, 3), (4, 4), (64, 64), (1000, 1)] {
+                let mut values = vec![None; grid.num_samples()];
+                {
+                    let tiles = grid.split_tiles(width, height, &mut values);
+                    let expected: Vec<_> = grid.iter_tiles(width, height).collect();
+                    assert_eq!(tiles.iter().map(|t| t.0).collect::<Vec<_>>(), expected);
+                    for (tile, mut rows) in tiles {
+                        assert_eq!(rows.len(), tile.height);
+                        let cells = rows.iter_mut().flat_map(|row| row.iter_mut());
+                        for (value, sample) in cells.zip(tile.iter_samples(&grid)) {
+                            assert!(value.is_none());
+                            *value = Some(sample);
+                        }
+                    }
+                }
+                let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+                assert_eq!(values, samples);
+            }
+        }
+    }
+
+    fn assert_near(a: (f64, f64), b: (f64, f64)) {
+        assert!((a.0 - b.0).abs() < 1e-12 && (a.1 - b.1).abs() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn centered_grids_have_square_cells() {
+        let grid = Grid::centered((-0.5, 0.25), 3.0, 300, 200);
+        assert_near(grid.center(), (-0.5, 0.25));
+        assert_near((grid.width(), grid.height()), (3.0, 2.0));
+        assert_near(grid.pixel_size(), (0.01, 0.01));
+        assert_near((grid.bounds().0, grid.bounds().1), (-2.0, 1.25));
+        assert_near((grid.bounds().2, grid.bounds().3), (1.0, -0.75));
+    }
+
+    #[test]
+    fn magnification_sets_the_width() {
+        let whole = Grid::magnified((-0.75, 0.0), 1.0, 400, 300);
+        assert_near((whole.width(), whole.height()), (UNIT_VIEW_WIDTH, 3.0));
+        assert!((whole.magnification() - 1.0).abs() < 1e-12);
+
+        let zoomed = Grid::magnified((-0.75, 0.1), 1e6, 400, 300);
+        assert_near(zoomed.center(), (-0.75, 0.1));
+        assert!((zoomed.width() - 4e-6).abs() < 1e-18);
+        assert!((zoomed.magnification() / 1e6 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pixels_and_points_convert_both_ways() {
+        for grid in grids() {
+            let (left, top, right, bottom) = grid.bounds();
+            assert_near(grid.pixel_to_complex(0.0, 0.0), (left, top));
+            let far = (grid.cells_wide() as f64, grid.cells_high() as f64);
+            assert_near(grid.pixel_to_complex(far.0, far.1), (right, bottom));
+
+            for &(x, y) in [(0.5, 0.5), (2.25, 0.75), (-3.0, 9.5)].iter() {
+                let (re, im) = grid.pixel_to_complex(x, y);
+                let (px, py) = grid.complex_to_pixel(re, im).unwrap();
+                assert!((px - x).abs() < 1e-6 && (py - y).abs() < 1e-6);
+            }
+
+            // Subgrids count pixels from their own corner.
+            let sub = grid.subgrid(grid.cells_wide() / 2, grid.cells_high() / 2, 1, 1);
+            let (re, im) = sub.pixel_to_complex(0.5, 0.5);
+            assert_near((re, im), sub.cell_position(0, 0).unwrap());
+            let (px, py) = sub.complex_to_pixel(re, im).unwrap();
+            assert!((px - 0.5).abs() < 1e-6 && (py - 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one cell")]
+    fn square_cells_need_a_nonempty_grid() {
+        Grid::centered((0.0, 0.0), 1.0, 0, 10);
+    }
+
+    #[test]
+    fn empty_tiles_and_strips_display() {
+        assert_eq!(format!("{}", GridTile::new(0, 0, 0, 0)), "Tile<Empty at column 0, row 0>");
+        assert_eq!(format!("{}", GridTile::new(3, 5, 2, 0)), "Tile<Empty at column 3, row 5>");
+        assert_eq!(format!("{}", GridTile::new(3, 5, 2, 1)), "Tile<Columns 3 to 4, rows 5 to 5>");
+        assert_eq!(format!("{}", GridStrip::new(4, 0)), "Strip<Empty at row 4>");
+        assert_eq!(format!("{}", GridStrip::new(4, 2)), "Strip<Rows 4 to 5>");
+    }
+}
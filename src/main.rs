@@ -1,6 +1,9 @@
 extern crate image;
 extern crate num;
 extern crate num_complex;
+extern crate flate2;
+extern crate gif;
+extern crate rayon;
 
 pub mod grid;
 pub mod mandelbrot;
@@ -8,20 +11,120 @@ pub mod fractal;
 pub mod runner;
 pub mod render;
 pub mod opt;
+pub mod encoding;
+pub mod sampling;
+pub mod view;
+pub mod plane;
+pub mod palette;
 
+use std::env;
 use std::fs;
+use std::io;
+use std::net;
 use std::path;
+use std::process;
 
 use render::{FractalRenderer};
 use runner::FractalRunner;
 
-fn main() {
-    const MAX_ITERS: usize = 500;
+const MAX_ITERS: usize = 500;
 
-    let grid = grid::Grid::new(-1.5, 1.0, 1.0, -1.0, 2000, 2000);
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
     let mandel = mandelbrot::Mandelbrot::new(MAX_ITERS);
 
-    let runner = runner::MultiThreadedRunner::new(mandel, 2);
+    match args.first().map(|arg| arg.as_str()) {
+        Some("worker") if args.len() == 2 => {
+            let listener = net::TcpListener::bind(args[1].as_str()).unwrap();
+            runner::network::serve::<mandelbrot::Mandelbrot>(listener).unwrap();
+        },
+        Some("render") if args.len() > 1 => {
+            let workers = args[1..].iter()
+                .map(|addr| addr.parse().unwrap())
+                .collect();
+            render(&runner::NetworkRunner::new(&mandel, workers));
+        },
+        Some("strip") if args.len() == 3 => {
+            let center = num_complex::Complex::new(args[1].parse().unwrap(),
+                                                   args[2].parse().unwrap());
+            render_strip(center, &mandel);
+        },
+        Some("frames") if args.len() == 3 => {
+            render_frames(&args[1], args[2].parse().unwrap());
+        },
+        Some("cycle") if args.len() == 2 => {
+            render_cycle(&mandel, args[1].parse().unwrap());
+        },
+        Some("resume") if args.len() == 2 => {
+            render_checkpointed(&args[1], &mandel);
+        },
+        None => render(&runner::MultiThreadedRunner::new(mandel.clone(), 2)),
+        _ => {
+            eprintln!("usage: fractal [worker LISTEN_ADDR | render WORKER_ADDR... | \
+                       strip RE IM | frames STRIP_PNG NUM_FRAMES | cycle NUM_FRAMES | \
+                       resume CHECKPOINT]");
+            process::exit(1);
+        }
+    }
+}
+
+const STRIP_WIDTH: usize = 1024;
+const FRAME_WIDTH: u32 = 1280;
+const FRAME_HEIGHT: u32 = 720;
+
+fn render_strip(center: num_complex::Complex<f64>, mandel: &mandelbrot::Mandelbrot) {
+    let map = render::ExponentialMap::new(center, 4.0, 1e-12, STRIP_WIDTH);
+    let runner = runner::RayonRunner::new(map.fractal(mandel.clone()));
+    let renderer = render::ExponentialMapRenderer::new(map,
+        render::map::LogarithmicMapper::new(MAX_ITERS, 100.0));
+
+    let image = renderer.render(&runner).unwrap();
+    image.to_luma().save("strip.png").unwrap();
+}
+
+fn render_frames(strip_path: &str, num_frames: usize) {
+    let strip = image::open(strip_path).unwrap().to_luma();
+    let (strip_width, strip_height) = strip.dimensions();
+    let depths = render::exponential::frame_depths(
+        strip_width, strip_height, FRAME_WIDTH, FRAME_HEIGHT, num_frames).unwrap();
+
+    for (i, depth) in depths.iter().enumerate() {
+        let frame = render::exponential::reconstruct_frame(
+            &strip, *depth, FRAME_WIDTH, FRAME_HEIGHT);
+        frame.save(format!("frame_{:05}.png", i)).unwrap();
+    }
+}
+
+fn render_cycle(mandel: &mandelbrot::Mandelbrot, num_frames: usize) {
+    let grid = grid::Grid::centered((-0.25, 0.0), 2.5, 640, 512);
+    let runner = runner::RayonRunner::new(mandel.clone());
+
+    let mut palette = palette::Colormap::Twilight.palette();
+    palette.set_repeat(4.0);
+    let cycler = render::PaletteCycler::new(
+        render::map::LogarithmicMapper::new(MAX_ITERS, 100.0), palette, num_frames);
+
+    let orbits = runner.run(&grid).unwrap();
+    let mapped = cycler.map(&grid, &orbits).unwrap();
+    let file = fs::File::create("cycle.gif").unwrap();
+    cycler.write_gif(io::BufWriter::new(file), &mapped).unwrap();
+}
+
+// Renders like `render`, recording progress in `checkpoint_path` so an
+// interrupted render picks up where it stopped.
+fn render_checkpointed(checkpoint_path: &str, mandel: &mandelbrot::Mandelbrot) {
+    let runner = runner::CheckpointRunner::new(
+        runner::MultiThreadedRunner::new(mandel.clone(), 2), checkpoint_path, 100);
+    render(&runner);
+
+    if let Err(err) = fs::remove_file(runner.path()) {
+        eprintln!("Couldn't remove checkpoint {}: {}", runner.path().display(), err);
+    }
+}
+
+fn render<R: FractalRunner>(runner: &R) {
+    let grid = grid::Grid::centered((-0.25, 0.0), 2.5, 2000, 1600);
+
     let renderer = 
     render::GrayscaleFractalRenderer::new(
         render::map::AntialiasMapper::new(1,
@@ -1,6 +1,7 @@
 use num_complex::{Complex};
 
-use fractal::{Fractal, FractalOrbit, EscapeTimeType};
+use fractal::{Fractal, FractalOrbit, FractalParameters, FromParameters, IncrementalFractal,
+              OrbitState, EscapeTimeType};
 
 #[derive(Clone)]
 pub struct Mandelbrot {
@@ -29,26 +30,26 @@ impl Mandelbrot {
         z*z + c
     }
 
-    fn run_iterations(&self, c: Complex<f64>) -> FractalOrbit {
+    fn run_iterations(&self, c: Complex<f64>, state: &mut OrbitState) -> FractalOrbit {
         const MAX_RADIUS_SQR: f64 = 4.0;
 
-        let mut z = Complex::new(0.0, 0.0);
-        let mut i: usize = 0;
-
-        while z.norm_sqr() < MAX_RADIUS_SQR && i <= self.iter_limit {
-            i += 1;
-            z = self.next_z(z, c);
+        let mut z = state.z;
+        let mut i = state.iterations;
 
+        let orbit = loop {
             if i >= self.iter_limit {
-                return FractalOrbit::Bounded;
+                break FractalOrbit::Bounded;
             }
-        }
+            if z.norm_sqr() >= MAX_RADIUS_SQR {
+                break FractalOrbit::Escaped(i as EscapeTimeType);
+            }
+            i += 1;
+            z = self.next_z(z, c);
+        };
 
-        if i == self.iter_limit {
-            FractalOrbit::Bounded
-        } else {
-            FractalOrbit::Escaped(i as EscapeTimeType)
-        }
+        state.z = z;
+        state.iterations = i;
+        orbit
     }
 }
 
@@ -58,6 +59,50 @@ impl Fractal for Mandelbrot {
             return FractalOrbit::Bounded;
         }
 
-        self.run_iterations(c)
+        self.run_iterations(c, &mut OrbitState::new())
     } 
 }
+
+impl IncrementalFractal for Mandelbrot {
+    fn iteration_limit(&self) -> usize {
+        self.iter_limit
+    }
+
+    fn with_iteration_limit(&self, iter_limit: usize) -> Self {
+        Mandelbrot::new(iter_limit)
+    }
+
+    fn continue_orbit(&self, c: Complex<f64>, state: &mut OrbitState) -> FractalOrbit {
+        if self.check_carteoid_inclusion(&c) {
+            return FractalOrbit::Bounded;
+        }
+
+        self.run_iterations(c, state)
+    }
+}
+
+impl FractalParameters for Mandelbrot {
+    fn parameters(&self) -> String {
+        format!("mandelbrot iter_limit={}", self.iter_limit)
+    }
+}
+
+impl FromParameters for Mandelbrot {
+    fn from_parameters(params: &str) -> Option<Self> {
+        let mut parts = params.split_whitespace();
+        if parts.next() != Some("mandelbrot") {
+            return None;
+        }
+
+        let mut iter_limit = None;
+        for part in parts {
+            let mut kv = part.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("iter_limit"), Some(val)) => iter_limit = val.parse().ok(),
+                _ => return None,
+            }
+        }
+
+        iter_limit.map(Mandelbrot::new)
+    }
+}
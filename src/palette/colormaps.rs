@@ -0,0 +1,94 @@
+use std::fmt;
+
+use super::{Color, ColorSpace, Palette};
+
+// Perceptually uniform colormaps from matplotlib. Each is stored as evenly
+// spaced samples of the original and blended in OKLab, which follows the
+// originals closely without carrying their full 256 entry tables.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Cividis,
+    // Starts and ends on the same color, for cyclic data or repeated palettes.
+    Twilight,
+}
+
+const VIRIDIS: [(u8, u8, u8); 10] = [
+    (0x44, 0x01, 0x54), (0x48, 0x28, 0x78), (0x3e, 0x4a, 0x89), (0x31, 0x68, 0x8e),
+    (0x26, 0x82, 0x8e), (0x1f, 0x9e, 0x89), (0x35, 0xb7, 0x79), (0x6d, 0xcd, 0x59),
+    (0xb4, 0xde, 0x2c), (0xfd, 0xe7, 0x25),
+];
+
+const MAGMA: [(u8, u8, u8); 10] = [
+    (0x00, 0x00, 0x04), (0x18, 0x0f, 0x3e), (0x45, 0x10, 0x77), (0x72, 0x1f, 0x81),
+    (0x9f, 0x2f, 0x7f), (0xcd, 0x40, 0x71), (0xf1, 0x60, 0x5d), (0xfd, 0x95, 0x67),
+    (0xfe, 0xc9, 0x8d), (0xfc, 0xfd, 0xbf),
+];
+
+const INFERNO: [(u8, u8, u8); 10] = [
+    (0x00, 0x00, 0x04), (0x1b, 0x0c, 0x42), (0x4b, 0x0c, 0x6b), (0x78, 0x1c, 0x6d),
+    (0xa5, 0x2c, 0x60), (0xcf, 0x44, 0x46), (0xed, 0x69, 0x25), (0xfb, 0x9a, 0x06),
+    (0xf7, 0xd0, 0x3c), (0xfc, 0xff, 0xa4),
+];
+
+const CIVIDIS: [(u8, u8, u8); 10] = [
+    (0x00, 0x20, 0x4d), (0x00, 0x33, 0x6f), (0x39, 0x48, 0x6b), (0x57, 0x5c, 0x6d),
+    (0x70, 0x71, 0x73), (0x8a, 0x87, 0x79), (0xa6, 0x9d, 0x75), (0xc4, 0xb5, 0x6c),
+    (0xe4, 0xcf, 0x5b), (0xff, 0xea, 0x46),
+];
+
+const TWILIGHT: [(u8, u8, u8); 9] = [
+    (0xe2, 0xd9, 0xe2), (0xa8, 0xb8, 0xce), (0x64, 0x88, 0xc3), (0x5e, 0x48, 0xa1),
+    (0x30, 0x12, 0x3c), (0x78, 0x22, 0x53), (0xb5, 0x55, 0x49), (0xd0, 0x9d, 0x8b),
+    (0xe2, 0xd9, 0xe2),
+];
+
+impl Colormap {
+    pub fn all() -> &'static [Colormap] {
+        &[Colormap::Viridis, Colormap::Magma, Colormap::Inferno, Colormap::Cividis,
+          Colormap::Twilight]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Colormap::all().iter().cloned().find(|colormap| colormap.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Colormap::Viridis => "viridis",
+            Colormap::Magma => "magma",
+            Colormap::Inferno => "inferno",
+            Colormap::Cividis => "cividis",
+            Colormap::Twilight => "twilight",
+        }
+    }
+
+    pub fn is_cyclic(&self) -> bool {
+        *self == Colormap::Twilight
+    }
+
+    pub fn palette(&self) -> Palette {
+        let samples: &[(u8, u8, u8)] = match *self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Cividis => &CIVIDIS,
+            Colormap::Twilight => &TWILIGHT,
+        };
+        let colors: Vec<_> = samples.iter()
+            .map(|&(r, g, b)| Color::from_rgb8(r, g, b))
+            .collect();
+
+        let mut palette = Palette::from_colors(&colors);
+        palette.set_color_space(ColorSpace::Oklab);
+        palette
+    }
+}
+
+impl fmt::Display for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,416 @@
+use std::f64::consts::PI;
+use std::io::{BufRead, Write};
+
+use super::{Color, ColorSpace, GradientStop, Interpolation, Palette, PaletteError,
+            PaletteResult};
+
+const GGR_HEADER: &str = "GIMP Gradient";
+
+const BLEND_LINEAR: u32 = 0;
+const BLEND_CURVED: u32 = 1;
+const BLEND_SINE: u32 = 2;
+const BLEND_SPHERE_INCREASING: u32 = 3;
+const BLEND_SPHERE_DECREASING: u32 = 4;
+const BLEND_STEP: u32 = 5;
+
+const COLOR_RGB: u32 = 0;
+const COLOR_HSV_CCW: u32 = 1;
+const COLOR_HSV_CW: u32 = 2;
+
+// Segments that linear stops can't represent exactly are sampled this many
+// times.
+const SEGMENT_SAMPLES: usize = 16;
+
+// Written segments approximating other blends are halved, up to
+// MAX_SUBDIVISIONS times, until they are within this of the gradient.
+const SEGMENT_TOLERANCE: f64 = 0.005;
+const MAX_SUBDIVISIONS: usize = 6;
+
+const MIDDLE_EPSILON: f64 = 1e-10;
+
+struct Segment {
+    left: f64,
+    middle: f64,
+    right: f64,
+    left_color: Color,
+    right_color: Color,
+    blend: u32,
+    coloring: u32,
+}
+
+// Reads a GIMP .ggr gradient. Linear and step RGB segments convert exactly;
+// curved, sine, spherical and HSV segments are sampled into linear stops.
+pub fn read_ggr<R: BufRead>(r: R) -> PaletteResult<Palette> {
+    let mut lines = r.lines().enumerate().map(|(index, line)| (index + 1, line));
+
+    match lines.next() {
+        Some((_, line)) => if line?.trim() != GGR_HEADER {
+            return Err(PaletteError::parse(1, "missing 'GIMP Gradient' header"));
+        },
+        None => return Err(PaletteError::parse(1, "empty gradient file")),
+    }
+
+    // Older files have no name line.
+    let (mut line_number, mut line) = next_line(&mut lines, 1)?;
+    if line.starts_with("Name:") {
+        let next = next_line(&mut lines, line_number)?;
+        line_number = next.0;
+        line = next.1;
+    }
+    let num_segments: usize = line.trim().parse()
+        .map_err(|_| PaletteError::parse(line_number, "expected the number of segments"))?;
+    if num_segments == 0 {
+        return Err(PaletteError::parse(line_number, "gradient has no segments"));
+    }
+
+    let mut stops = Vec::new();
+    for _ in 0..num_segments {
+        let next = next_line(&mut lines, line_number)?;
+        line_number = next.0;
+        let segment = parse_segment(&next.1, line_number)?;
+        add_segment_stops(&segment, &mut stops);
+    }
+
+    Ok(Palette::new(stops))
+}
+
+fn next_line<I>(lines: &mut I, last_line: usize) -> PaletteResult<(usize, String)>
+        where I: Iterator<Item=(usize, ::std::io::Result<String>)> {
+    match lines.next() {
+        Some((line_number, line)) => Ok((line_number, line?)),
+        None => Err(PaletteError::parse(last_line, "unexpected end of file")),
+    }
+}
+
+fn parse_segment(line: &str, line_number: usize) -> PaletteResult<Segment> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    if fields.len() < 13 {
+        return Err(PaletteError::parse(line_number, "segment needs at least 13 fields"));
+    }
+
+    let mut values = [0.0; 11];
+    for (value, field) in values.iter_mut().zip(fields.iter()) {
+        *value = match field.parse::<f64>() {
+            Ok(number) if number.is_finite() => number,
+            _ => return Err(PaletteError::parse(
+                line_number, &format!("invalid number '{}'", field))),
+        };
+    }
+    let parse_type = |field: &str, max: u32| match field.parse::<u32>() {
+        Ok(value) if value <= max => Ok(value),
+        _ => Err(PaletteError::parse(line_number, &format!("invalid segment type '{}'", field))),
+    };
+    let blend = parse_type(fields[11], BLEND_STEP)?;
+    let coloring = parse_type(fields[12], COLOR_HSV_CW)?;
+
+    let (left, middle, right) = (values[0], values[1], values[2]);
+    if !(0.0 <= left && left <= middle && middle <= right && right <= 1.0) {
+        return Err(PaletteError::parse(line_number, "segment positions out of order"));
+    }
+
+    Ok(Segment{left: left, middle: middle, right: right,
+        left_color: Color::with_alpha(values[3], values[4], values[5], values[6]),
+        right_color: Color::with_alpha(values[7], values[8], values[9], values[10]),
+        blend: blend, coloring: coloring})
+}
+
+fn add_segment_stops(segment: &Segment, stops: &mut Vec<GradientStop>) {
+    let width = segment.right - segment.left;
+    let left = GradientStop::new(segment.left, segment.left_color);
+    let right = GradientStop::new(segment.right, segment.right_color);
+
+    if segment.coloring == COLOR_RGB && segment.blend == BLEND_LINEAR {
+        let halfway = segment.left_color.lerp(&segment.right_color, 0.5);
+        stops.extend_from_slice(&[left, GradientStop::new(segment.middle, halfway), right]);
+    } else if segment.coloring == COLOR_RGB && segment.blend == BLEND_STEP {
+        stops.extend_from_slice(&[left, GradientStop::new(segment.middle, segment.left_color),
+                                  GradientStop::new(segment.middle, segment.right_color), right]);
+    } else if width <= 0.0 {
+        stops.extend_from_slice(&[left, right]);
+    } else {
+        let middle = (segment.middle - segment.left) / width;
+        for sample in 0..(SEGMENT_SAMPLES + 1) {
+            let pos = (sample as f64) / (SEGMENT_SAMPLES as f64);
+            let factor = blend_factor(segment.blend, middle, pos);
+            stops.push(GradientStop::new(segment.left + pos * width,
+                                         segment_color(segment, factor)));
+        }
+    }
+}
+
+// GIMP's blend functions, from a position within the segment to how far to
+// go from the left color to the right one.
+fn blend_factor(blend: u32, middle: f64, pos: f64) -> f64 {
+    match blend {
+        BLEND_CURVED => if middle < MIDDLE_EPSILON {
+            1.0
+        } else {
+            pos.powf(0.5f64.ln() / middle.ln())
+        },
+        BLEND_SINE => ((-PI / 2.0 + PI * linear_factor(middle, pos)).sin() + 1.0) / 2.0,
+        BLEND_SPHERE_INCREASING => {
+            let pos = linear_factor(middle, pos) - 1.0;
+            (1.0 - pos * pos).sqrt()
+        }
+        BLEND_SPHERE_DECREASING => {
+            let pos = linear_factor(middle, pos);
+            1.0 - (1.0 - pos * pos).sqrt()
+        }
+        BLEND_STEP => if pos >= middle { 1.0 } else { 0.0 },
+        _ => linear_factor(middle, pos),
+    }
+}
+
+fn linear_factor(middle: f64, pos: f64) -> f64 {
+    if pos <= middle {
+        if middle < MIDDLE_EPSILON { 0.0 } else { 0.5 * pos / middle }
+    } else {
+        let rest = 1.0 - middle;
+        if rest < MIDDLE_EPSILON { 1.0 } else { 0.5 + 0.5 * (pos - middle) / rest }
+    }
+}
+
+fn segment_color(segment: &Segment, factor: f64) -> Color {
+    let (left, right) = (&segment.left_color, &segment.right_color);
+    if segment.coloring == COLOR_RGB {
+        return left.lerp(right, factor);
+    }
+
+    let (left_hue, left_sat, left_val) = left.to_hsv();
+    let (right_hue, right_sat, right_val) = right.to_hsv();
+    let mut hue = if segment.coloring == COLOR_HSV_CCW {
+        if left_hue < right_hue {
+            left_hue + (right_hue - left_hue) * factor
+        } else {
+            left_hue + (1.0 - (left_hue - right_hue)) * factor
+        }
+    } else if right_hue < left_hue {
+        left_hue - (left_hue - right_hue) * factor
+    } else {
+        left_hue - (1.0 - (right_hue - left_hue)) * factor
+    };
+    hue -= hue.floor();
+
+    Color::from_hsv(hue,
+                    left_sat + (right_sat - left_sat) * factor,
+                    left_val + (right_val - left_val) * factor,
+                    left.a + (right.a - left.a) * factor)
+}
+
+// Pushes a linear RGB segment from `start` to `end` following the gradient,
+// split in half while it strays from it.
+fn push_sampled(segments: &mut Vec<(f64, Color, f64, Color)>, palette: &Palette,
+                start: f64, end: f64, subdivisions: usize) {
+    let width = end - start;
+    // Sample just inside the span so the gradient doesn't pick up the next
+    // stop's color at its end.
+    let left = palette.gradient_at(start);
+    let right = palette.gradient_at(end - width * MIDDLE_EPSILON);
+    let follows = [0.25, 0.5, 0.75].iter().all(|t| {
+        let (a, b) = (left.lerp(&right, *t), palette.gradient_at(start + width * t));
+        (a.r - b.r).abs().max((a.g - b.g).abs()).max((a.b - b.b).abs()).max((a.a - b.a).abs())
+            <= SEGMENT_TOLERANCE
+    });
+
+    if follows || subdivisions == 0 {
+        segments.push((start, left, end, right));
+    } else {
+        let middle = start + width / 2.0;
+        push_sampled(segments, palette, start, middle, subdivisions - 1);
+        push_sampled(segments, palette, middle, end, subdivisions - 1);
+    }
+}
+
+// Writes the palette's gradient, without repetition, offset or interior color,
+// as one segment between each pair of stops. Gradients GIMP's segments can't
+// match exactly, such as smooth or Catmull-Rom ones or those blended outside
+// sRGB, are sampled into linear segments.
+pub fn write_ggr<W: Write>(w: &mut W, name: &str, palette: &Palette) -> PaletteResult<()> {
+    let stops = palette.stops();
+    let mut segments = Vec::new();
+    if stops.len() == 1 || stops[0].position > 0.0 {
+        let first = &stops[0];
+        segments.push((0.0, first.color, first.position.max(0.0), first.color));
+    }
+    // GIMP blends segments in RGB, so only linear blends in sRGB map onto
+    // segments exactly; anything else is sampled.
+    let exact = palette.color_space() == ColorSpace::Srgb;
+    for pair in stops.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        match palette.interpolation() {
+            Interpolation::Linear if exact =>
+                segments.push((from.position, from.color, to.position, to.color)),
+            Interpolation::Step => segments.push((from.position, from.color, to.position, from.color)),
+            _ => {
+                let span = to.position - from.position;
+                for sample in 0..SEGMENT_SAMPLES {
+                    let start = from.position + span * (sample as f64) / (SEGMENT_SAMPLES as f64);
+                    let end = from.position + span * ((sample + 1) as f64) / (SEGMENT_SAMPLES as f64);
+                    push_sampled(&mut segments, palette, start, end, MAX_SUBDIVISIONS);
+                }
+            }
+        }
+    }
+    let last = &stops[stops.len() - 1];
+    if stops.len() > 1 && last.position < 1.0 {
+        segments.push((last.position.max(0.0), last.color, 1.0, last.color));
+    }
+
+    // Zero-width segments only mark hard edges, which adjacent segments with
+    // different end colors already show.
+    segments.retain(|segment| segment.2 > segment.0);
+    if segments.is_empty() {
+        let color = palette.gradient_at(0.0);
+        segments.push((0.0, color, 1.0, color));
+    }
+
+    writeln!(w, "{}", GGR_HEADER)?;
+    writeln!(w, "Name: {}", name)?;
+    writeln!(w, "{}", segments.len())?;
+    for &(left, left_color, right, right_color) in segments.iter() {
+        let (left, right) = (left.clamp(0.0, 1.0), right.clamp(0.0, 1.0));
+        writeln!(w, "{} {} {} {} {} {} {} {} {} {} {} {} {}",
+                 left, (left + right) / 2.0, right,
+                 left_color.r, left_color.g, left_color.b, left_color.a,
+                 right_color.r, right_color.g, right_color.b, right_color.a,
+                 BLEND_LINEAR, COLOR_RGB)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(result: PaletteResult<Palette>) -> usize {
+        match result {
+            Err(PaletteError::ParseError{line, ..}) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    fn close(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-9 && (a.g - b.g).abs() < 1e-9
+            && (a.b - b.b).abs() < 1e-9 && (a.a - b.a).abs() < 1e-9
+    }
+
+    // A single black to white segment with the given blend and coloring.
+    fn segment_file(blend: u32, coloring: u32, right_color: &str) -> String {
+        format!("GIMP Gradient\nName: Test\n1\n0 0.5 1 0 0 0 1 {} 1 {} {}\n",
+                right_color, blend, coloring)
+    }
+
+    #[test]
+    fn reads_linear_and_step_segments_exactly() {
+        let ggr = "GIMP Gradient\nName: Two segments\n2\n\
+                   0 0.25 0.5 1 0 0 1 0 0 1 1 0 0\n\
+                   0.5 0.75 1 0 1 0 1 1 1 1 0.5 5 0\n";
+        let palette = read_ggr(ggr.as_bytes()).unwrap();
+        assert!(close(palette.gradient_at(0.0), Color::new(1.0, 0.0, 0.0)));
+        assert!(close(palette.gradient_at(0.25), Color::new(0.5, 0.0, 0.5)));
+        assert!(close(palette.gradient_at(0.5), Color::new(0.0, 1.0, 0.0)));
+        assert!(close(palette.gradient_at(0.7), Color::new(0.0, 1.0, 0.0)));
+        assert!(close(palette.gradient_at(0.8), Color::with_alpha(1.0, 1.0, 1.0, 0.5)));
+
+        // Files from before gradients had names.
+        let unnamed = "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n";
+        assert!(close(read_ggr(unnamed.as_bytes()).unwrap().gradient_at(0.5),
+                      Color::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn samples_blend_functions() {
+        let expected = [(BLEND_LINEAR, 0.25),
+                        (BLEND_CURVED, 0.25),
+                        (BLEND_SINE, (1.0 - 0.5f64.sqrt()) / 2.0),
+                        (BLEND_SPHERE_INCREASING, (1.0 - 0.75 * 0.75f64).sqrt()),
+                        (BLEND_SPHERE_DECREASING, 1.0 - (1.0 - 0.25 * 0.25f64).sqrt()),
+                        (BLEND_STEP, 0.0)];
+        for &(blend, value) in expected.iter() {
+            let palette = read_ggr(segment_file(blend, COLOR_RGB, "1 1 1").as_bytes()).unwrap();
+            assert!(close(palette.gradient_at(0.25), Color::new(value, value, value)),
+                    "blend {} gave {:?}", blend, palette.gradient_at(0.25));
+            assert!(close(palette.gradient_at(0.0), Color::new(0.0, 0.0, 0.0)));
+            assert!(close(palette.gradient_at(1.0), Color::new(1.0, 1.0, 1.0)));
+        }
+    }
+
+    #[test]
+    fn hsv_segments_go_around_the_hue_circle() {
+        let red_to_green = |coloring| format!(
+            "GIMP Gradient\n1\n0 0.5 1 1 0 0 1 0 1 0 1 0 {}\n", coloring);
+        let ccw = read_ggr(red_to_green(COLOR_HSV_CCW).as_bytes()).unwrap();
+        assert!(close(ccw.gradient_at(0.5), Color::new(1.0, 1.0, 0.0)));
+        let cw = read_ggr(red_to_green(COLOR_HSV_CW).as_bytes()).unwrap();
+        assert!(close(cw.gradient_at(0.5), Color::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(error_line(read_ggr("".as_bytes())), 1);
+        assert_eq!(error_line(read_ggr("GIMP Palette\n".as_bytes())), 1);
+        assert_eq!(error_line(read_ggr("GIMP Gradient\nName: x\n".as_bytes())), 2);
+        assert_eq!(error_line(read_ggr("GIMP Gradient\nName: x\ntwo\n".as_bytes())), 3);
+        assert_eq!(error_line(read_ggr("GIMP Gradient\nName: x\n0\n".as_bytes())), 3);
+        assert_eq!(error_line(read_ggr("GIMP Gradient\nName: x\n2\n\
+                                        0 0.5 1 0 0 0 1 1 1 1 1 0 0\n".as_bytes())), 4);
+
+        let bad_segments = ["0 0.5 1 0 0 0 1 1 1 1 1 0",
+                            "0 0.5 1 0 0 0 1 1 1 1 1 6 0",
+                            "0 0.5 1 0 0 0 1 1 1 1 1 0 3",
+                            "0 0.5 1 nan 0 0 1 1 1 1 1 0 0",
+                            "0 0.5 inf 0 0 0 1 1 1 1 1 0 0",
+                            "0.5 0.25 1 0 0 0 1 1 1 1 1 0 0"];
+        for segment in bad_segments.iter() {
+            let ggr = format!("GIMP Gradient\nName: x\n1\n{}\n", segment);
+            assert_eq!(error_line(read_ggr(ggr.as_bytes())), 4, "{}", segment);
+        }
+    }
+
+    #[test]
+    fn written_gradient_reads_back() {
+        let mut palette = Palette::new(vec![
+            GradientStop::new(0.2, Color::new(1.0, 0.0, 0.0)),
+            GradientStop::new(0.6, Color::with_alpha(0.0, 0.0, 1.0, 0.5)),
+            GradientStop::new(0.9, Color::new(0.0, 1.0, 0.0))]);
+        let spaces = [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Oklab,
+                      ColorSpace::Lab];
+        for &interpolation in [Interpolation::Linear, Interpolation::Smooth].iter() {
+            for &space in spaces.iter() {
+                palette.set_interpolation(interpolation);
+                palette.set_color_space(space);
+                let mut written = Vec::new();
+                write_ggr(&mut written, "Round trip", &palette).unwrap();
+                let read = read_ggr(&written[..]).unwrap();
+                for i in 0..101 {
+                    let position = (i as f64) / 100.0;
+                    let (a, b) = (read.gradient_at(position), palette.gradient_at(position));
+                    assert!((a.r - b.r).abs() < 0.02 && (a.g - b.g).abs() < 0.02
+                            && (a.b - b.b).abs() < 0.02 && (a.a - b.a).abs() < 0.02,
+                            "{:?} in {:?} at {}: {:?} != {:?}",
+                            interpolation, space, position, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_srgb_linear_gradients_are_written_exactly() {
+        let mut palette = Palette::new(vec![
+            GradientStop::new(0.0, Color::new(1.0, 0.0, 0.0)),
+            GradientStop::new(1.0, Color::new(0.0, 0.0, 1.0))]);
+        let segments = |palette: &Palette| {
+            let mut written = Vec::new();
+            write_ggr(&mut written, "Segments", palette).unwrap();
+            read_ggr(&written[..]).unwrap();
+            String::from_utf8(written).unwrap().lines().nth(2).unwrap().to_string()
+        };
+
+        assert_eq!(segments(&palette), "1");
+        for &space in [ColorSpace::LinearRgb, ColorSpace::Oklab, ColorSpace::Hsv].iter() {
+            palette.set_color_space(space);
+            let segments: usize = segments(&palette).parse().unwrap();
+            assert!(segments >= SEGMENT_SAMPLES, "{:?} wrote {} segments", space, segments);
+        }
+    }
+}
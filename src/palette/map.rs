@@ -0,0 +1,95 @@
+use std::io::{BufRead, Write};
+
+use super::{Color, Palette, PaletteError, PaletteResult};
+
+// Fractint palettes have this many entries, and this writes that many.
+pub const MAP_ENTRIES: usize = 256;
+
+// Reads a Fractint .map file: one "red green blue" entry per line, each 0 to
+// 255, with anything after the third number treated as a comment. Entries
+// become evenly spaced stops.
+pub fn read_map<R: BufRead>(r: R) -> PaletteResult<Palette> {
+    let mut colors = Vec::new();
+    for (index, line) in r.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut channels = [0u8; 3];
+        let mut fields = line.split_whitespace();
+        for channel in channels.iter_mut() {
+            let field = fields.next()
+                .ok_or_else(|| PaletteError::parse(line_number, "expected red, green and blue"))?;
+            *channel = field.parse()
+                .map_err(|_| PaletteError::parse(
+                    line_number, &format!("invalid channel value '{}'", field)))?;
+        }
+        colors.push(Color::from_rgb8(channels[0], channels[1], channels[2]));
+    }
+
+    if colors.is_empty() {
+        return Err(PaletteError::parse(1, "no colors in map file"));
+    }
+    Ok(Palette::from_colors(&colors))
+}
+
+// Writes the palette's gradient, without repetition, offset or interior color,
+// as MAP_ENTRIES evenly spaced entries.
+pub fn write_map<W: Write>(w: &mut W, palette: &Palette) -> PaletteResult<()> {
+    for entry in 0..MAP_ENTRIES {
+        let position = (entry as f64) / ((MAP_ENTRIES - 1) as f64);
+        let rgba = palette.gradient_at(position).to_rgba8();
+        writeln!(w, "{} {} {}", rgba[0], rgba[1], rgba[2])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(result: PaletteResult<Palette>) -> usize {
+        match result {
+            Err(PaletteError::ParseError{line, ..}) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_entries_as_even_stops() {
+        let map = "255 0 0 red\n\n0 255 0\n  0 0 255   blue, with a comment\n";
+        let palette = read_map(map.as_bytes()).unwrap();
+        let positions: Vec<_> = palette.stops().iter().map(|stop| stop.position).collect();
+        assert_eq!(positions, vec![0.0, 0.5, 1.0]);
+        assert_eq!(palette.gradient_at(0.0).to_rgba8(), [255, 0, 0, 255]);
+        assert_eq!(palette.gradient_at(0.5).to_rgba8(), [0, 255, 0, 255]);
+        assert_eq!(palette.gradient_at(1.0).to_rgba8(), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(error_line(read_map("0 0 0\n\n1 2\n".as_bytes())), 3);
+        assert_eq!(error_line(read_map("0 0 0\n256 0 0\n".as_bytes())), 2);
+        assert_eq!(error_line(read_map("0 x 0\n".as_bytes())), 1);
+        assert_eq!(error_line(read_map("\n\n".as_bytes())), 1);
+    }
+
+    #[test]
+    fn written_map_reads_back() {
+        let palette = Palette::from_colors(&[Color::from_rgb8(10, 20, 30),
+                                             Color::from_rgb8(200, 100, 0)]);
+        let mut written = Vec::new();
+        write_map(&mut written, &palette).unwrap();
+        assert_eq!(written.iter().filter(|&&c| c == b'\n').count(), MAP_ENTRIES);
+
+        let read = read_map(&written[..]).unwrap();
+        for i in 0..11 {
+            let position = (i as f64) / 10.0;
+            let (a, b) = (read.gradient_at(position).to_rgba8(),
+                          palette.gradient_at(position).to_rgba8());
+            assert!(a.iter().zip(b.iter()).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 1));
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use std::cmp::{self, Ordering};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use render::map::MappedCellIntensity;
+
+pub mod colormaps;
+pub mod ggr;
+pub mod map;
+pub mod space;
+pub mod ugr;
+pub use self::colormaps::Colormap;
+pub use self::ggr::{read_ggr, write_ggr};
+pub use self::map::{read_map, write_map};
+pub use self::space::ColorSpace;
+pub use self::ugr::read_ugr;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    IoError(io::Error),
+    // A malformed gradient file, with the 1-based line the problem was found on.
+    ParseError {
+        line: usize,
+        message: String,
+    },
+    UnsupportedFormat(String),
+}
+type PaletteResult<T> = Result<T, PaletteError>;
+
+// A color with channels in [0, 1].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GradientStop {
+    pub position: f64,
+    pub color: Color,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    // Each stop's color holds until the next stop.
+    Step,
+    Linear,
+    // Linear, eased in and out of every stop.
+    Smooth,
+    // Catmull-Rom spline through the stops, which avoids visible kinks where
+    // the slope of the gradient changes.
+    CatmullRom,
+}
+
+// A gradient over [0, 1] built from stops, blended in `color_space`. Before the
+// first stop and after the last one the gradient holds the end colors. The
+// gradient is repeated `repeat` times over the mapped intensity range and
+// shifted by `offset`, both in units of the whole gradient.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    stops: Vec<GradientStop>,
+    interpolation: Interpolation,
+    color_space: ColorSpace,
+    repeat: f64,
+    offset: f64,
+    interior: Color,
+}
+
+impl Color {
+    pub fn new(r: f64, g: f64, b: f64) -> Self {
+        Color{r: r, g: g, b: b, a: 1.0}
+    }
+
+    pub fn with_alpha(r: f64, g: f64, b: f64, a: f64) -> Self {
+        Color{r: r, g: g, b: b, a: a}
+    }
+
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Self {
+        Color::new((r as f64) / 255.0, (g as f64) / 255.0, (b as f64) / 255.0)
+    }
+
+    pub fn to_rgba8(&self) -> [u8; 4] {
+        [channel_to_u8(self.r), channel_to_u8(self.g), channel_to_u8(self.b),
+         channel_to_u8(self.a)]
+    }
+
+    pub fn black() -> Self {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Hue, saturation and value, all in [0, 1].
+    pub fn to_hsv(&self) -> (f64, f64, f64) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+        if delta <= 0.0 {
+            return (0.0, 0.0, max);
+        }
+
+        let sector = if max == self.r {
+            (self.g - self.b) / delta
+        } else if max == self.g {
+            (self.b - self.r) / delta + 2.0
+        } else {
+            (self.r - self.g) / delta + 4.0
+        };
+        let hue = sector / 6.0;
+        (hue - hue.floor(), delta / max, max)
+    }
+
+    pub fn from_hsv(hue: f64, saturation: f64, value: f64, alpha: f64) -> Self {
+        let sector = (hue - hue.floor()) * 6.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - ((sector % 2.0) - 1.0).abs());
+        let (r, g, b) = match sector as usize {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let min = value - chroma;
+        Color::with_alpha(r + min, g + min, b + min, alpha)
+    }
+
+    // The color with every channel clamped to [0, 1].
+    pub fn clipped(&self) -> Color {
+        let clip = |channel: f64| channel.clamp(0.0, 1.0);
+        Color::with_alpha(clip(self.r), clip(self.g), clip(self.b), clip(self.a))
+    }
+
+    pub fn lerp(&self, other: &Color, t: f64) -> Color {
+        Color::with_alpha(self.r + (other.r - self.r) * t,
+                          self.g + (other.g - self.g) * t,
+                          self.b + (other.b - self.b) * t,
+                          self.a + (other.a - self.a) * t)
+    }
+}
+
+fn channel_to_u8(val: f64) -> u8 {
+    (val.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl GradientStop {
+    pub fn new(position: f64, color: Color) -> Self {
+        GradientStop{position: position, color: color}
+    }
+}
+
+impl Palette {
+    pub fn new(mut stops: Vec<GradientStop>) -> Self {
+        if stops.is_empty() {
+            panic!("Palette needs at least one stop");
+        }
+        if stops.iter().any(|stop| stop.position.is_nan()) {
+            panic!("Palette stop positions must be numbers");
+        }
+        stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap_or(Ordering::Equal));
+
+        Palette{stops: stops, interpolation: Interpolation::Linear,
+            color_space: ColorSpace::Srgb, repeat: 1.0, offset: 0.0, interior: Color::black()}
+    }
+
+    // Stops evenly spaced over [0, 1].
+    pub fn from_colors(colors: &[Color]) -> Self {
+        let last = cmp::max(colors.len(), 2) - 1;
+        Palette::new(colors.iter().enumerate()
+            .map(|(i, color)| GradientStop::new((i as f64) / (last as f64), *color))
+            .collect())
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    pub fn set_repeat(&mut self, repeat: f64) {
+        self.repeat = repeat;
+    }
+
+    pub fn set_offset(&mut self, offset: f64) {
+        self.offset = offset;
+    }
+
+    pub fn set_interior(&mut self, interior: Color) {
+        self.interior = interior;
+    }
+
+    pub fn stops(&self) -> &[GradientStop] {
+        &self.stops
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn repeat(&self) -> f64 {
+        self.repeat
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    pub fn interior(&self) -> Color {
+        self.interior
+    }
+
+    pub fn color(&self, intensity: MappedCellIntensity) -> Color {
+        match intensity {
+            MappedCellIntensity::BoundedValue => self.interior,
+            MappedCellIntensity::EscapedValue(val) => self.color_at(val),
+        }
+    }
+
+    // Color for a mapped intensity, after repetition and offset.
+    pub fn color_at(&self, val: f64) -> Color {
+        let scaled = val * self.repeat + self.offset;
+        let mut position = scaled - scaled.floor();
+        // Keep the end of each repetition on the last stop rather than
+        // wrapping back to the first.
+        if position == 0.0 && scaled > 0.0 {
+            position = 1.0;
+        }
+        self.gradient_at(position)
+    }
+
+    // Color of the gradient itself at `position` in [0, 1].
+    pub fn gradient_at(&self, position: f64) -> Color {
+        let stops = &self.stops;
+        let next = match stops.iter().position(|stop| stop.position > position) {
+            Some(0) => return stops[0].color,
+            Some(next) => next,
+            None => return stops[stops.len() - 1].color,
+        };
+
+        let from = &stops[next - 1];
+        let to = &stops[next];
+        let span = to.position - from.position;
+        let t = if span > 0.0 { (position - from.position) / span } else { 1.0 };
+
+        let space = self.color_space;
+        match self.interpolation {
+            Interpolation::Step => from.color,
+            Interpolation::Linear => space.lerp(&from.color, &to.color, t),
+            Interpolation::Smooth => space.lerp(&from.color, &to.color, t * t * (3.0 - 2.0 * t)),
+            Interpolation::CatmullRom => {
+                let before = &stops[if next >= 2 { next - 2 } else { next - 1 }].color;
+                let after = &stops[cmp::min(next + 1, stops.len() - 1)].color;
+                space.catmull_rom(before, &from.color, &to.color, after, t)
+            }
+        }
+    }
+}
+
+impl PaletteError {
+    pub fn parse(line: usize, message: &str) -> Self {
+        PaletteError::ParseError{line: line, message: message.to_string()}
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaletteError::IoError(ref err) => err.fmt(f),
+            PaletteError::ParseError{line, ref message} =>
+                write!(f, "line {}: {}", line, message),
+            PaletteError::UnsupportedFormat(ref name) =>
+                write!(f, "unsupported gradient format: {}", name),
+        }
+    }
+}
+
+impl Error for PaletteError {
+    fn description(&self) -> &str {
+        match *self {
+            PaletteError::IoError(ref err) => err.description(),
+            PaletteError::ParseError{ref message, ..} => message,
+            PaletteError::UnsupportedFormat(_) => "unsupported gradient format",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            PaletteError::IoError(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(other: io::Error) -> Self {
+        PaletteError::IoError(other)
+    }
+}
+
+// Loads a palette from a Fractint .map, UltraFractal .ugr or GIMP .ggr file,
+// chosen by extension. Of the gradients in a .ugr file, this loads the first.
+pub fn load_palette<P: AsRef<Path>>(path: P) -> PaletteResult<Palette> {
+    let path = path.as_ref();
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    let reader = io::BufReader::new(fs::File::open(path)?);
+
+    match extension.as_str() {
+        "map" => read_map(reader),
+        "ggr" => read_ggr(reader),
+        "ugr" => Ok(read_ugr(reader)?.remove(0).1),
+        _ => Err(PaletteError::UnsupportedFormat(path.display().to_string())),
+    }
+}
+
+impl Default for Palette {
+    // Deep blue through white and orange to black and back, so that it repeats
+    // without a seam.
+    fn default() -> Self {
+        Palette::new(vec![
+            GradientStop::new(0.0, Color::from_rgb8(0, 7, 100)),
+            GradientStop::new(0.16, Color::from_rgb8(32, 107, 203)),
+            GradientStop::new(0.42, Color::from_rgb8(237, 255, 255)),
+            GradientStop::new(0.6425, Color::from_rgb8(255, 170, 0)),
+            GradientStop::new(0.8575, Color::from_rgb8(0, 2, 0)),
+            GradientStop::new(1.0, Color::from_rgb8(0, 7, 100)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Color {
+        Color::new(1.0, 0.0, 0.0)
+    }
+
+    fn blue() -> Color {
+        Color::new(0.0, 0.0, 1.0)
+    }
+
+    fn assert_color(actual: Color, expected: Color) {
+        assert!((actual.r - expected.r).abs() < 1e-12 && (actual.g - expected.g).abs() < 1e-12
+                && (actual.b - expected.b).abs() < 1e-12 && (actual.a - expected.a).abs() < 1e-12,
+                "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn stops_are_sorted_and_ends_held() {
+        let palette = Palette::new(vec![GradientStop::new(0.75, blue()),
+                                        GradientStop::new(0.25, red())]);
+        assert_eq!(palette.stops()[0].color, red());
+        assert_color(palette.gradient_at(0.0), red());
+        assert_color(palette.gradient_at(0.25), red());
+        assert_color(palette.gradient_at(0.5), Color::new(0.5, 0.0, 0.5));
+        assert_color(palette.gradient_at(0.75), blue());
+        assert_color(palette.gradient_at(1.0), blue());
+
+        let single = Palette::new(vec![GradientStop::new(0.5, red())]);
+        assert_color(single.gradient_at(0.0), red());
+        assert_color(single.gradient_at(1.0), red());
+    }
+
+    #[test]
+    fn interpolation_modes() {
+        let mut palette = Palette::from_colors(&[Color::black(), Color::new(1.0, 1.0, 1.0)]);
+        let gray = |palette: &Palette, position| palette.gradient_at(position).r;
+        assert!((gray(&palette, 0.25) - 0.25).abs() < 1e-12);
+
+        palette.set_interpolation(Interpolation::Step);
+        assert_eq!(gray(&palette, 0.25), 0.0);
+        assert_eq!(gray(&palette, 0.99), 0.0);
+        assert_eq!(gray(&palette, 1.0), 1.0);
+
+        palette.set_interpolation(Interpolation::Smooth);
+        assert!(gray(&palette, 0.25) < 0.25 && (gray(&palette, 0.5) - 0.5).abs() < 1e-12);
+
+        // Between interior stops evenly spaced along a line, the spline is
+        // the line; it still passes through every stop.
+        let grays: Vec<_> = (0..4).map(|i| i as f64 / 3.0)
+            .map(|v| Color::new(v, v, v))
+            .collect();
+        let mut spline = Palette::from_colors(&grays);
+        spline.set_interpolation(Interpolation::CatmullRom);
+        for &position in [0.4, 0.5, 0.6].iter() {
+            assert!((gray(&spline, position) - position).abs() < 1e-12);
+        }
+        for (i, stop) in spline.stops().iter().enumerate() {
+            assert!((gray(&spline, stop.position) - grays[i].r).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn repeat_and_offset_cycle_the_gradient() {
+        let mut palette = Palette::from_colors(&[red(), blue()]);
+        assert_color(palette.color_at(0.0), red());
+        assert_color(palette.color_at(1.0), blue());
+
+        palette.set_repeat(2.0);
+        assert_color(palette.color_at(0.25), palette.gradient_at(0.5));
+        assert_color(palette.color_at(0.75), palette.gradient_at(0.5));
+        // Every repetition ends on the last stop rather than the first.
+        assert_color(palette.color_at(0.5), blue());
+        assert_color(palette.color_at(1.0), blue());
+
+        palette.set_repeat(1.0);
+        palette.set_offset(0.25);
+        assert_color(palette.color_at(0.0), palette.gradient_at(0.25));
+        assert_color(palette.color_at(0.875), palette.gradient_at(0.125));
+    }
+
+    #[test]
+    fn bounded_cells_use_the_interior() {
+        let mut palette = Palette::default();
+        assert_eq!(palette.color(MappedCellIntensity::BoundedValue), Color::black());
+        palette.set_interior(red());
+        assert_eq!(palette.color(MappedCellIntensity::BoundedValue), red());
+        assert_color(palette.color(MappedCellIntensity::EscapedValue(0.3)),
+                     palette.color_at(0.3));
+
+        // The default gradient starts and ends on the same color.
+        assert_eq!(palette.color_at(0.0), palette.color_at(1.0));
+    }
+
+    #[test]
+    fn channels_round_and_clip_to_bytes() {
+        assert_eq!(Color::with_alpha(-0.5, 0.5, 1.5, 1.0).to_rgba8(), [0, 128, 255, 255]);
+        assert_eq!(Color::from_rgb8(12, 200, 255).to_rgba8(), [12, 200, 255, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one stop")]
+    fn palettes_need_stops() {
+        Palette::new(Vec::new());
+    }
+}
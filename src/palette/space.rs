@@ -0,0 +1,275 @@
+use std::f64::consts::PI;
+
+use super::Color;
+
+// The space gradients are interpolated in. Stops are always given in sRGB.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    LinearRgb,
+    Oklab,
+    // OKLab in polar form, interpolating hue the short way around.
+    Oklch,
+    // CIELAB under a D65 white point.
+    Lab,
+    Hsv,
+}
+
+// Below this chroma or saturation a color's hue carries no information, so
+// interpolation takes the hue from the other end instead.
+const ACHROMATIC: f64 = 1e-6;
+
+const D65_WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" => Some(ColorSpace::LinearRgb),
+            "oklab" => Some(ColorSpace::Oklab),
+            "oklch" => Some(ColorSpace::Oklch),
+            "lab" => Some(ColorSpace::Lab),
+            "hsv" => Some(ColorSpace::Hsv),
+            _ => None,
+        }
+    }
+
+    // Coordinates of `color` in this space, with alpha last. Hues are in
+    // turns rather than degrees.
+    pub fn to_coords(&self, color: &Color) -> [f64; 4] {
+        let (x, y, z) = match *self {
+            ColorSpace::Srgb => (color.r, color.g, color.b),
+            ColorSpace::LinearRgb => linear_rgb(color),
+            ColorSpace::Oklab => oklab(color),
+            ColorSpace::Oklch => {
+                let (l, a, b) = oklab(color);
+                let hue = b.atan2(a) / (2.0 * PI);
+                (l, a.hypot(b), hue - hue.floor())
+            }
+            ColorSpace::Lab => lab(color),
+            ColorSpace::Hsv => color.to_hsv(),
+        };
+        [x, y, z, color.a]
+    }
+
+    // The sRGB color at `coords`, clipped to the sRGB gamut.
+    pub fn from_coords(&self, coords: [f64; 4]) -> Color {
+        let [x, y, z, alpha] = coords;
+        let color = match *self {
+            ColorSpace::Srgb => Color::with_alpha(x, y, z, alpha),
+            ColorSpace::LinearRgb => from_linear_rgb(x, y, z, alpha),
+            ColorSpace::Oklab => from_oklab(x, y, z, alpha),
+            ColorSpace::Oklch => {
+                let angle = z * 2.0 * PI;
+                from_oklab(x, y * angle.cos(), y * angle.sin(), alpha)
+            }
+            ColorSpace::Lab => from_lab(x, y, z, alpha),
+            ColorSpace::Hsv => Color::from_hsv(wrap_hue(x), y.clamp(0.0, 1.0),
+                                               z.max(0.0), alpha),
+        };
+        color.clipped()
+    }
+
+    // Index of the hue coordinate and of the coordinate that says how much
+    // the hue matters, for the polar spaces.
+    fn hue_coords(&self) -> Option<(usize, usize)> {
+        match *self {
+            ColorSpace::Oklch => Some((2, 1)),
+            ColorSpace::Hsv => Some((0, 1)),
+            _ => None,
+        }
+    }
+
+    pub fn lerp(&self, from: &Color, to: &Color, t: f64) -> Color {
+        let mut points = [self.to_coords(from), self.to_coords(to)];
+        self.unwrap_hues(&mut points, 0, 1);
+        let (a, b) = (points[0], points[1]);
+        let mut coords = [0.0; 4];
+        for i in 0..4 {
+            coords[i] = a[i] + (b[i] - a[i]) * t;
+        }
+        self.from_coords(coords)
+    }
+
+    // Catmull-Rom spline from `p1` to `p2`, with `p0` and `p3` the stops on
+    // either side.
+    pub fn catmull_rom(&self, p0: &Color, p1: &Color, p2: &Color, p3: &Color, t: f64) -> Color {
+        let mut points = [self.to_coords(p0), self.to_coords(p1),
+                          self.to_coords(p2), self.to_coords(p3)];
+        self.unwrap_hues(&mut points, 1, 2);
+        let mut coords = [0.0; 4];
+        for i in 0..4 {
+            let (a, b, c, d) = (points[0][i], points[1][i], points[2][i], points[3][i]);
+            coords[i] = 0.5 * ((2.0 * b) + (-a + c) * t
+                + (2.0 * a - 5.0 * b + 4.0 * c - d) * t * t
+                + (-a + 3.0 * b - 3.0 * c + d) * t * t * t);
+        }
+        // Alpha isn't clipped with the color, so the spline can overshoot it.
+        coords[3] = coords[3].clamp(0.0, 1.0);
+        self.from_coords(coords)
+    }
+
+    // Gives achromatic points the hue of the segment's other end, then shifts
+    // every hue by whole turns to lie within half a turn of the previous one,
+    // so interpolating goes the short way around.
+    fn unwrap_hues(&self, points: &mut [[f64; 4]], from: usize, to: usize) {
+        let (hue, weight) = match self.hue_coords() {
+            Some(coords) => coords,
+            None => return,
+        };
+
+        let reference = if points[from][weight] > ACHROMATIC { from } else { to };
+        let reference_hue = points[reference][hue];
+        for point in points.iter_mut() {
+            if point[weight] <= ACHROMATIC {
+                point[hue] = reference_hue;
+            }
+        }
+        for i in 1..points.len() {
+            let previous = points[i - 1][hue];
+            points[i][hue] -= (points[i][hue] - previous).round();
+        }
+    }
+}
+
+fn wrap_hue(hue: f64) -> f64 {
+    hue - hue.floor()
+}
+
+fn to_linear(channel: f64) -> f64 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(channel: f64) -> f64 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.max(0.0).powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn linear_rgb(color: &Color) -> (f64, f64, f64) {
+    (to_linear(color.r), to_linear(color.g), to_linear(color.b))
+}
+
+fn from_linear_rgb(r: f64, g: f64, b: f64, alpha: f64) -> Color {
+    Color::with_alpha(from_linear(r), from_linear(g), from_linear(b), alpha)
+}
+
+// Björn Ottosson's OKLab, from linear sRGB.
+fn oklab(color: &Color) -> (f64, f64, f64) {
+    let (r, g, b) = linear_rgb(color);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+     1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+     0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s)
+}
+
+fn from_oklab(l: f64, a: f64, b: f64, alpha: f64) -> Color {
+    let l_ = l + 0.3963377774 * a + 0.2158037573 * b;
+    let m_ = l - 0.1055613458 * a - 0.0638541728 * b;
+    let s_ = l - 0.0894841775 * a - 1.2914855480 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    from_linear_rgb(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+                    -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+                    -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+                    alpha)
+}
+
+fn lab(color: &Color) -> (f64, f64, f64) {
+    let (r, g, b) = linear_rgb(color);
+    let xyz = [0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+               0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+               0.0193339 * r + 0.1191920 * g + 0.9503041 * b];
+    let f = |i: usize| {
+        let t = xyz[i] / D65_WHITE[i];
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(0), f(1), f(2));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+fn from_lab(l: f64, a: f64, b: f64, alpha: f64) -> Color {
+    let fy = (l + 16.0) / 116.0;
+    let f = [fy + a / 500.0, fy, fy - b / 200.0];
+    let mut xyz = [0.0; 3];
+    for i in 0..3 {
+        let cube = f[i] * f[i] * f[i];
+        let t = if cube > 216.0 / 24389.0 {
+            cube
+        } else {
+            (116.0 * f[i] - 16.0) * 27.0 / 24389.0
+        };
+        xyz[i] = t * D65_WHITE[i];
+    }
+    let [x, y, z] = xyz;
+    from_linear_rgb(3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+                    -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+                    0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+                    alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 6] = [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Oklab,
+                                     ColorSpace::Oklch, ColorSpace::Lab, ColorSpace::Hsv];
+
+    fn assert_color(actual: Color, expected: Color) {
+        assert!((actual.r - expected.r).abs() < 1e-3 && (actual.g - expected.g).abs() < 1e-3
+                && (actual.b - expected.b).abs() < 1e-3 && (actual.a - expected.a).abs() < 1e-3,
+                "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn coords_round_trip() {
+        let colors = [Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0),
+                      Color::with_alpha(0.8, 0.3, 0.1, 0.5), Color::new(0.2, 0.6, 0.9)];
+        for space in SPACES.iter() {
+            for color in colors.iter() {
+                assert_color(space.from_coords(space.to_coords(color)), *color);
+                assert_color(space.lerp(color, &Color::new(0.5, 0.5, 0.5), 0.0), *color);
+            }
+        }
+
+        let white = ColorSpace::Oklab.to_coords(&Color::new(1.0, 1.0, 1.0));
+        assert!((white[0] - 1.0).abs() < 1e-3 && white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+        let white = ColorSpace::Lab.to_coords(&Color::new(1.0, 1.0, 1.0));
+        assert!((white[0] - 100.0).abs() < 1e-2 && white[1].abs() < 1e-2 && white[2].abs() < 1e-2);
+    }
+
+    #[test]
+    fn spaces_blend_differently() {
+        let (black, white) = (Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        assert_color(ColorSpace::Srgb.lerp(&black, &white, 0.5), Color::new(0.5, 0.5, 0.5));
+        let gray = ColorSpace::LinearRgb.lerp(&black, &white, 0.5);
+        assert!((gray.r - 0.735).abs() < 1e-3, "{:?}", gray);
+    }
+
+    #[test]
+    fn hues_go_the_short_way_around() {
+        let (red, magenta) = (Color::new(1.0, 0.0, 0.0), Color::new(1.0, 0.0, 1.0));
+        assert_color(ColorSpace::Hsv.lerp(&red, &magenta, 0.5), Color::new(1.0, 0.0, 0.5));
+
+        let middle = ColorSpace::Oklch.lerp(&red, &magenta, 0.5);
+        assert!(middle.g < 0.1, "{:?}", middle);
+
+        // Gray takes its hue from the other end instead of blending toward red.
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let blue = Color::new(0.0, 0.0, 1.0);
+        let middle = ColorSpace::Hsv.lerp(&gray, &blue, 0.5);
+        assert!(middle.r < middle.b && (middle.r - middle.g).abs() < 1e-9, "{:?}", middle);
+    }
+}
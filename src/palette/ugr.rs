@@ -0,0 +1,301 @@
+use std::io::BufRead;
+
+use super::{Color, GradientStop, Interpolation, Palette, PaletteError, PaletteResult};
+
+// UltraFractal gradients have this many positions and wrap around at the end.
+const UGR_POSITIONS: f64 = 400.0;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Section {
+    Header,
+    Gradient,
+    Opacity,
+}
+
+// One gradient entry while it's being read.
+struct Entry {
+    name: String,
+    line: usize,
+    section: Section,
+    index: Option<(usize, f64)>,
+    smooth: bool,
+    rotation: f64,
+    colors: Vec<(f64, Color)>,
+    opacities: Vec<(f64, f64)>,
+}
+
+// Reads every gradient in an UltraFractal .ugr file, with its entry name.
+// Gradients wrap around, so this adds stops at both ends with the color
+// between the last and first points. Smooth gradients interpolate with
+// Catmull-Rom splines, and opacity points set the alpha of each stop.
+pub fn read_ugr<R: BufRead>(r: R) -> PaletteResult<Vec<(String, Palette)>> {
+    let mut palettes = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut last_line = 0;
+
+    for (index, line) in r.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        last_line = line_number;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if entry.is_none() {
+            if !trimmed.ends_with('{') {
+                return Err(PaletteError::parse(line_number, "expected a gradient entry"));
+            }
+            let name = trimmed[..trimmed.len() - 1].trim().to_string();
+            entry = Some(Entry{name: name, line: line_number, section: Section::Header,
+                index: None, smooth: false, rotation: 0.0,
+                colors: Vec::new(), opacities: Vec::new()});
+            continue;
+        }
+
+        if trimmed == "}" {
+            let finished = entry.take().unwrap();
+            let palette = finished.palette()?;
+            palettes.push((finished.name, palette));
+            continue;
+        }
+
+        let current = entry.as_mut().unwrap();
+        match trimmed {
+            "gradient:" => current.start_section(Section::Gradient)?,
+            "opacity:" => current.start_section(Section::Opacity)?,
+            _ => {
+                for (key, value) in tokens(trimmed, line_number)? {
+                    current.set(&key, &value, line_number)?;
+                }
+            }
+        }
+    }
+
+    if let Some(unfinished) = entry {
+        return Err(PaletteError::parse(
+            last_line, &format!("gradient '{}' is missing its closing '}}'", unfinished.name)));
+    }
+    if palettes.is_empty() {
+        return Err(PaletteError::parse(last_line, "no gradients in file"));
+    }
+    Ok(palettes)
+}
+
+// Splits a line into key=value pairs; values may be quoted.
+fn tokens(line: &str, line_number: usize) -> PaletteResult<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let equals = rest.find('=')
+            .ok_or_else(|| PaletteError::parse(
+                line_number, &format!("expected key=value, found '{}'", rest)))?;
+        let key = rest[..equals].trim().to_string();
+        rest = &rest[equals + 1..];
+
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"')
+                .ok_or_else(|| PaletteError::parse(line_number, "unterminated quoted value"))?;
+            let value = rest[1..end + 1].to_string();
+            rest = &rest[end + 2..];
+            value
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let value = rest[..end].to_string();
+            rest = &rest[end..];
+            value
+        };
+        pairs.push((key, value));
+        rest = rest.trim_start();
+    }
+    Ok(pairs)
+}
+
+fn parse_number(value: &str, line_number: usize) -> PaletteResult<f64> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(PaletteError::parse(line_number, &format!("invalid number '{}'", value))),
+    }
+}
+
+impl Entry {
+    fn start_section(&mut self, section: Section) -> PaletteResult<()> {
+        if let Some((line_number, _)) = self.index {
+            return Err(PaletteError::parse(line_number, "index without a color or opacity"));
+        }
+        self.section = section;
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str, line_number: usize) -> PaletteResult<()> {
+        match key {
+            "smooth" if self.section == Section::Gradient => self.smooth = value == "yes",
+            "rotation" => self.rotation = parse_number(value, line_number)?,
+            "index" => {
+                let index = parse_number(value, line_number)?;
+                self.index = Some((line_number, index));
+            }
+            "color" | "opacity" => {
+                let index = match self.index.take() {
+                    Some((_, index)) => index,
+                    None => return Err(PaletteError::parse(
+                        line_number, &format!("{} without an index", key))),
+                };
+                let position = wrap_position(index / UGR_POSITIONS);
+                if key == "color" {
+                    let color: u32 = value.parse()
+                        .map_err(|_| PaletteError::parse(
+                            line_number, &format!("invalid color '{}'", value)))?;
+                    // Colors are stored as 0xBBGGRR.
+                    self.colors.push((position, Color::from_rgb8(
+                        color as u8, (color >> 8) as u8, (color >> 16) as u8)));
+                } else {
+                    let opacity = parse_number(value, line_number)?;
+                    self.opacities.push((position, (opacity / 255.0).clamp(0.0, 1.0)));
+                }
+            }
+            // Titles and settings this palette has no use for.
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn palette(&self) -> PaletteResult<Palette> {
+        if let Some((line_number, _)) = self.index {
+            return Err(PaletteError::parse(line_number, "index without a color or opacity"));
+        }
+        if self.colors.is_empty() {
+            return Err(PaletteError::parse(
+                self.line, &format!("gradient '{}' has no colors", self.name)));
+        }
+
+        let mut colors = self.colors.clone();
+        colors.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut opacities = self.opacities.clone();
+        opacities.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let (first, last) = (colors[0], colors[colors.len() - 1]);
+        let gap = first.0 + 1.0 - last.0;
+        let seam = if gap > 0.0 {
+            last.1.lerp(&first.1, (1.0 - last.0) / gap)
+        } else {
+            first.1
+        };
+
+        let mut stops = vec![GradientStop::new(0.0, seam)];
+        stops.extend(colors.iter().map(|&(position, color)| GradientStop::new(position, color)));
+        stops.push(GradientStop::new(1.0, seam));
+        for stop in stops.iter_mut() {
+            if !opacities.is_empty() {
+                stop.color.a = wrapped_opacity(&opacities, stop.position);
+            }
+        }
+
+        let mut palette = Palette::new(stops);
+        if self.smooth {
+            palette.set_interpolation(Interpolation::CatmullRom);
+        }
+        palette.set_offset(wrap_position(-self.rotation / UGR_POSITIONS));
+        Ok(palette)
+    }
+}
+
+fn wrap_position(position: f64) -> f64 {
+    position - position.floor()
+}
+
+// Linear interpolation between sorted opacity points, wrapping around at 1.
+fn wrapped_opacity(points: &[(f64, f64)], position: f64) -> f64 {
+    let next = points.iter().position(|point| point.0 >= position);
+    let (before, after) = match next {
+        Some(0) => ((points[points.len() - 1].0 - 1.0, points[points.len() - 1].1), points[0]),
+        Some(next) => (points[next - 1], points[next]),
+        None => (points[points.len() - 1], (points[0].0 + 1.0, points[0].1)),
+    };
+
+    let span = after.0 - before.0;
+    if span > 0.0 {
+        before.1 + (after.1 - before.1) * (position - before.0) / span
+    } else {
+        after.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(result: PaletteResult<Vec<(String, Palette)>>) -> usize {
+        match result {
+            Err(PaletteError::ParseError{line, ..}) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    fn close(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-9 && (a.g - b.g).abs() < 1e-9
+            && (a.b - b.b).abs() < 1e-9 && (a.a - b.a).abs() < 1e-9
+    }
+
+    const TWO_GRADIENTS: &str = "\
+first {
+gradient:
+  title=\"Red and blue\" smooth=no rotation=100
+  index=0 color=255
+  index=200 color=16711680
+opacity:
+  smooth=no index=0 opacity=255
+  index=200 opacity=0
+}
+
+second {
+gradient:
+  smooth=yes
+  index=100 color=65280
+}
+";
+
+    #[test]
+    fn reads_every_gradient() {
+        let palettes = read_ugr(TWO_GRADIENTS.as_bytes()).unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].0, "first");
+        assert_eq!(palettes[1].0, "second");
+
+        let first = &palettes[0].1;
+        assert_eq!(first.interpolation(), Interpolation::Linear);
+        assert_eq!(first.offset(), 0.75);
+        assert!(close(first.gradient_at(0.0), Color::new(1.0, 0.0, 0.0)));
+        assert!(close(first.gradient_at(0.25), Color::with_alpha(0.5, 0.0, 0.5, 0.5)));
+        assert!(close(first.gradient_at(0.5), Color::with_alpha(0.0, 0.0, 1.0, 0.0)));
+        // The gradient wraps, so it ends where it started.
+        assert!(close(first.gradient_at(1.0), first.gradient_at(0.0)));
+
+        let second = &palettes[1].1;
+        assert_eq!(second.interpolation(), Interpolation::CatmullRom);
+        for i in 0..5 {
+            assert!(close(second.gradient_at((i as f64) / 4.0), Color::new(0.0, 1.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let with_line = |line: &str| TWO_GRADIENTS.replace("  index=200 color=16711680", line);
+        assert_eq!(error_line(read_ugr(with_line("  index=nan color=255").as_bytes())), 5);
+        assert_eq!(error_line(read_ugr(with_line("  index=inf color=255").as_bytes())), 5);
+        assert_eq!(error_line(read_ugr(with_line("  index=200 color=blue").as_bytes())), 5);
+        assert_eq!(error_line(read_ugr(with_line("  color=255").as_bytes())), 5);
+        assert_eq!(error_line(read_ugr(with_line("  index=200").as_bytes())), 5);
+        assert_eq!(error_line(read_ugr(with_line("  index=\"200 color=255").as_bytes())), 5);
+
+        let rotated = TWO_GRADIENTS.replace("rotation=100", "rotation=inf");
+        assert_eq!(error_line(read_ugr(rotated.as_bytes())), 3);
+        let opacity = TWO_GRADIENTS.replace("opacity=0", "opacity=NaN");
+        assert_eq!(error_line(read_ugr(opacity.as_bytes())), 8);
+
+        assert_eq!(error_line(read_ugr("first {\ngradient:\n}\n".as_bytes())), 1);
+        assert_eq!(error_line(read_ugr("first {\n  index=0 color=255\n".as_bytes())), 2);
+        assert_eq!(error_line(read_ugr("\nindex=0 color=255\n".as_bytes())), 2);
+        assert_eq!(error_line(read_ugr("\n\n".as_bytes())), 2);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use num_complex::{Complex};
+use fractal::{Fractal, FractalOrbit, FractalParameters, FromParameters, IncrementalFractal,
+              OrbitState};
+
+// Maps a point of the plane a grid is laid over to the parameter handed to a
+// fractal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlaneTransform {
+    // c = 1/mu, which shows the neighbourhood of infinity around the origin.
+    Inverted,
+    // c = lambda/2 - lambda^2/4, so the grid covers the lambda plane of the
+    // logistic map lambda*z*(1 - z).
+    Lambda,
+    // c = mu + offset.
+    Shifted(Complex<f64>),
+    // c = center + exp(mu). A grid with angle across and log radius down, like
+    // `ExponentialMap::grid`, covers the annuli around `center` at every scale
+    // with the same detail.
+    Exponential(Complex<f64>),
+}
+
+// Runs `fractal` on the transformed parameter plane. Transforms chain by
+// wrapping a `TransformedFractal` in another one, innermost applied last.
+#[derive(Clone)]
+pub struct TransformedFractal<T> {
+    fractal: T,
+    transform: PlaneTransform,
+}
+
+impl PlaneTransform {
+    pub fn apply(&self, mu: Complex<f64>) -> Complex<f64> {
+        match *self {
+            PlaneTransform::Inverted => mu.inv(),
+            PlaneTransform::Lambda => mu * 0.5 - mu * mu * 0.25,
+            PlaneTransform::Shifted(offset) => mu + offset,
+            PlaneTransform::Exponential(center) => center + mu.exp(),
+        }
+    }
+
+    // The point of the transformed plane that maps to `c`. Where several do,
+    // this picks the lambda with real part at least 1 (the plane is symmetric
+    // about re = 1) and the logarithm with imaginary part in (-pi, pi].
+    pub fn invert(&self, c: Complex<f64>) -> Option<Complex<f64>> {
+        let one = Complex::new(1.0, 0.0);
+        let mu = match *self {
+            PlaneTransform::Inverted => c.inv(),
+            PlaneTransform::Lambda => one + (one - c * 4.0).sqrt(),
+            PlaneTransform::Shifted(offset) => c - offset,
+            PlaneTransform::Exponential(center) => (c - center).ln(),
+        };
+        if mu.re.is_finite() && mu.im.is_finite() {
+            Some(mu)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_parameters(params: &str) -> Option<Self> {
+        let mut parts = params.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("inverted"), None) => Some(PlaneTransform::Inverted),
+            (Some("lambda"), None) => Some(PlaneTransform::Lambda),
+            (Some("shifted"), Some(point)) => parse_point(point).map(PlaneTransform::Shifted),
+            (Some("exp"), Some(point)) => parse_point(point).map(PlaneTransform::Exponential),
+            _ => None,
+        }
+    }
+}
+
+fn parse_point(point: &str) -> Option<Complex<f64>> {
+    let mut parts = point.splitn(2, ',');
+    match (parts.next().and_then(|re| re.parse().ok()),
+           parts.next().and_then(|im| im.parse().ok())) {
+        (Some(re), Some(im)) => Some(Complex::new(re, im)),
+        _ => None,
+    }
+}
+
+impl fmt::Display for PlaneTransform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlaneTransform::Inverted => write!(f, "inverted"),
+            PlaneTransform::Lambda => write!(f, "lambda"),
+            PlaneTransform::Shifted(offset) => write!(f, "shifted:{},{}", offset.re, offset.im),
+            PlaneTransform::Exponential(center) => write!(f, "exp:{},{}", center.re, center.im),
+        }
+    }
+}
+
+impl<T: Fractal> TransformedFractal<T> {
+    pub fn new(fractal: T, transform: PlaneTransform) -> Self {
+        TransformedFractal{fractal: fractal, transform: transform}
+    }
+
+    pub fn fractal(&self) -> &T {
+        &self.fractal
+    }
+
+    pub fn transform(&self) -> PlaneTransform {
+        self.transform
+    }
+}
+
+impl<T: Fractal> Fractal for TransformedFractal<T> {
+    fn test(&self, c: Complex<f64>) -> FractalOrbit {
+        self.fractal.test(self.transform.apply(c))
+    }
+}
+
+impl<T: IncrementalFractal> IncrementalFractal for TransformedFractal<T> {
+    fn iteration_limit(&self) -> usize {
+        self.fractal.iteration_limit()
+    }
+
+    fn with_iteration_limit(&self, iter_limit: usize) -> Self {
+        TransformedFractal::new(self.fractal.with_iteration_limit(iter_limit), self.transform)
+    }
+
+    fn continue_orbit(&self, c: Complex<f64>, state: &mut OrbitState) -> FractalOrbit {
+        self.fractal.continue_orbit(self.transform.apply(c), state)
+    }
+}
+
+impl<T: FractalParameters> FractalParameters for TransformedFractal<T> {
+    fn parameters(&self) -> String {
+        format!("{} plane={}", self.fractal.parameters(), self.transform)
+    }
+}
+
+impl<T: Fractal + FromParameters> FromParameters for TransformedFractal<T> {
+    fn from_parameters(params: &str) -> Option<Self> {
+        let mut parts = params.rsplitn(2, ' ');
+        let transform = match parts.next() {
+            Some(part) if part.starts_with("plane=") =>
+                PlaneTransform::from_parameters(&part["plane=".len()..])?,
+            _ => return None,
+        };
+        let fractal = T::from_parameters(parts.next()?)?;
+        Some(TransformedFractal::new(fractal, transform))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use mandelbrot::Mandelbrot;
+    use super::*;
+
+    fn assert_close(actual: Complex<f64>, expected: Complex<f64>) {
+        assert!((actual - expected).norm() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn transforms_map_known_points() {
+        let c = Complex::new;
+        assert_close(PlaneTransform::Inverted.apply(c(2.0, 0.0)), c(0.5, 0.0));
+        assert_close(PlaneTransform::Inverted.apply(c(0.0, 1.0)), c(0.0, -1.0));
+        assert_close(PlaneTransform::Lambda.apply(c(1.0, 0.0)), c(0.25, 0.0));
+        assert_close(PlaneTransform::Lambda.apply(c(2.0, 0.0)), c(0.0, 0.0));
+        assert_close(PlaneTransform::Shifted(c(-1.0, 0.5)).apply(c(0.25, 0.25)), c(-0.75, 0.75));
+
+        // Log radius in the real part, angle in the imaginary part.
+        let exp = PlaneTransform::Exponential(c(-0.75, 0.1));
+        assert_close(exp.apply(c(0.5f64.ln(), 0.5 * PI)), c(-0.75, 0.6));
+        assert_close(exp.apply(c(0.0, PI)), c(-1.75, 0.1));
+    }
+
+    #[test]
+    fn inverse_transforms_round_trip() {
+        let transforms = [PlaneTransform::Inverted, PlaneTransform::Lambda,
+                          PlaneTransform::Shifted(Complex::new(0.3, -0.2)),
+                          PlaneTransform::Exponential(Complex::new(-0.75, 0.1))];
+        let points = [Complex::new(0.3, 0.4), Complex::new(-1.5, 0.01), Complex::new(2.0, -3.0)];
+        for transform in transforms.iter() {
+            for c in points.iter() {
+                let mu = transform.invert(*c).unwrap();
+                assert_close(transform.apply(mu), *c);
+            }
+        }
+
+        // Where several points map to c, the documented branch is picked.
+        for c in points.iter() {
+            assert!(PlaneTransform::Lambda.invert(*c).unwrap().re >= 1.0);
+            let angle = PlaneTransform::Exponential(Complex::new(0.0, 0.0)).invert(*c).unwrap().im;
+            assert!(angle > -PI && angle <= PI);
+        }
+        assert_close(PlaneTransform::Lambda.invert(Complex::new(0.0, 0.0)).unwrap(),
+                     Complex::new(2.0, 0.0));
+
+        // Points that nothing maps to have no inverse.
+        assert_eq!(PlaneTransform::Inverted.invert(Complex::new(0.0, 0.0)), None);
+        let center = Complex::new(-0.75, 0.1);
+        assert_eq!(PlaneTransform::Exponential(center).invert(center), None);
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let fractal = TransformedFractal::new(
+            TransformedFractal::new(Mandelbrot::new(300), PlaneTransform::Inverted),
+            PlaneTransform::Exponential(Complex::new(-0.75, 0.125)));
+        let params = fractal.parameters();
+        assert_eq!(params, "mandelbrot iter_limit=300 plane=inverted plane=exp:-0.75,0.125");
+
+        let read = TransformedFractal::<TransformedFractal<Mandelbrot>>::from_parameters(&params)
+            .unwrap();
+        assert_eq!(read.transform(), fractal.transform());
+        assert_eq!(read.fractal().transform(), PlaneTransform::Inverted);
+        assert_eq!(read.parameters(), params);
+
+        assert_eq!(PlaneTransform::from_parameters("exp:1"), None);
+        assert_eq!(PlaneTransform::from_parameters("rotated"), None);
+    }
+}
@@ -0,0 +1,236 @@
+use rayon::prelude::*;
+
+use grid;
+use fractal::FractalOrbit;
+use runner::{FractalRunner, RunnerError};
+use render::{RenderError, RenderResult};
+use sampling::SamplePattern;
+use super::map::{RowMapper, MappedCellIntensity, MappingResult, average_samples};
+use super::map::MappedCellIntensity::{EscapedValue, BoundedValue};
+
+// Renders a grid once at one sample per cell, then supersamples only the cells
+// whose mapped intensity differs from a neighbour by more than `threshold`, or
+// that border the interior. Each such cell is sampled again with `pattern`, and
+// the samples are averaged the same way `AntialiasMapper` does.
+//
+// Cells and subsamples are mapped one value at a time, so the mapper is a
+// `RowMapper`. Mappers that need statistics collect them from the base pass
+// alone, and map both passes with them.
+pub struct AdaptiveAntialiaser<M> {
+    mapper: M,
+    threshold: f64,
+    pattern: SamplePattern,
+}
+
+impl<M: RowMapper> AdaptiveAntialiaser<M> {
+    pub fn new(mapper: M, threshold: f64, pattern: SamplePattern) -> Self {
+        if pattern.samples_per_cell() == 0 {
+            panic!("pattern must take at least one sample per cell");
+        }
+        AdaptiveAntialiaser{mapper: mapper, threshold: threshold, pattern: pattern}
+    }
+
+    pub fn map<R: FractalRunner + Sync>(&self, runner: &R, grid: &grid::Grid)
+            -> RenderResult<MappingResult> {
+        let width = grid.cells_wide();
+        let height = grid.cells_high();
+
+        let mut base_grid = grid.clone();
+        base_grid.set_sample_pattern(SamplePattern::Center);
+        let base = runner.run(&base_grid)?;
+        if base.len() != width * height {
+            return Err(RenderError::OtherError(
+                "Runner produced the wrong number of samples".to_string()));
+        }
+
+        let mut stats = self.mapper.new_statistics();
+        if self.mapper.needs_statistics() {
+            self.mapper.accumulate(&mut stats, &base);
+            self.mapper.finish_statistics(&mut stats);
+        }
+        let mut values = self.mapper.map_row(&stats, &base);
+        let edges = edge_spans(&values, width, height, self.threshold);
+
+        // Edge cells are sampled again a run of neighbouring cells at a time.
+        let mut sample_grid = grid.clone();
+        sample_grid.set_sample_pattern(self.pattern);
+        let sample_grid = &sample_grid;
+        let subsamples: Result<Vec<Vec<FractalOrbit>>, RunnerError> = edges.par_iter()
+            .map(|tile| runner.run(&sample_grid.tile_grid(tile)))
+            .collect();
+
+        let samples_per_cell = self.pattern.samples_per_cell();
+        for (tile, samples) in edges.iter().zip(subsamples?.iter()) {
+            if samples.len() != sample_grid.num_samples_in_tile(tile) {
+                return Err(RenderError::OtherError(
+                    "Runner produced the wrong number of samples".to_string()));
+            }
+            let mapped = self.mapper.map_row(&stats, samples);
+            let start = tile.y * width + tile.x;
+            for (value, cell) in values[start..start + tile.width].iter_mut()
+                    .zip(mapped.chunks(samples_per_cell)) {
+                *value = average_samples(cell);
+            }
+        }
+
+        Ok(MappingResult{values: values, width: width, height: height})
+    }
+}
+
+// Runs of horizontally adjacent edge cells, as one row high tiles.
+fn edge_spans(values: &[MappedCellIntensity], width: usize, height: usize, threshold: f64)
+        -> Vec<grid::GridTile> {
+    let mut spans = Vec::new();
+    for y in 0..height {
+        let mut span: Option<grid::GridTile> = None;
+        for x in 0..width {
+            if is_edge(values, width, height, x, y, threshold) {
+                match span {
+                    Some(ref mut span) => span.width += 1,
+                    None => span = Some(grid::GridTile::new(x, y, 1, 1)),
+                }
+            } else if let Some(span) = span.take() {
+                spans.push(span);
+            }
+        }
+        spans.extend(span);
+    }
+    spans
+}
+
+fn is_edge(values: &[MappedCellIntensity], width: usize, height: usize,
+           x: usize, y: usize, threshold: f64) -> bool {
+    let index = y * width + x;
+    let neighbours = [
+        (x > 0, index.wrapping_sub(1)),
+        (x + 1 < width, index + 1),
+        (y > 0, index.wrapping_sub(width)),
+        (y + 1 < height, index + width),
+    ];
+    neighbours.iter()
+        .filter(|&&(valid, _)| valid)
+        .any(|&(_, neighbour)| differs(values[index], values[neighbour], threshold))
+}
+
+fn differs(a: MappedCellIntensity, b: MappedCellIntensity, threshold: f64) -> bool {
+    match (a, b) {
+        (BoundedValue, BoundedValue) => false,
+        (EscapedValue(a), EscapedValue(b)) => (a - b).abs() > threshold,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use mandelbrot::Mandelbrot;
+    use runner::SyncronousRunner;
+    use render::map::{HistogramLinearMapper, LinearMapper, OrbitMapper};
+    use super::*;
+
+    // Counts the samples it computes.
+    struct CountingRunner {
+        runner: SyncronousRunner<Mandelbrot>,
+        samples: AtomicUsize,
+    }
+
+    impl CountingRunner {
+        fn new() -> Self {
+            CountingRunner{runner: SyncronousRunner::new(Mandelbrot::new(100)),
+                samples: AtomicUsize::new(0)}
+        }
+
+        fn take_count(&self) -> usize {
+            self.samples.swap(0, Ordering::SeqCst)
+        }
+    }
+
+    impl FractalRunner for CountingRunner {
+        fn run(&self, grid: &grid::Grid) -> Result<Vec<FractalOrbit>, RunnerError> {
+            self.samples.fetch_add(grid.num_samples(), Ordering::SeqCst);
+            self.runner.run(grid)
+        }
+    }
+
+    fn value(intensity: MappedCellIntensity) -> f64 {
+        match intensity {
+            EscapedValue(val) => val,
+            BoundedValue => 1.0,
+        }
+    }
+
+    fn mean_error(a: &MappingResult, b: &MappingResult) -> f64 {
+        assert_eq!((a.width, a.height), (b.width, b.height));
+        let total: f64 = a.values.iter().zip(b.values.iter())
+            .map(|(a, b)| (value(*a) - value(*b)).abs())
+            .sum();
+        total / (a.values.len() as f64)
+    }
+
+    fn grid() -> grid::Grid {
+        grid::Grid::centered((-0.75, 0.1), 0.6, 64, 48)
+    }
+
+    #[test]
+    fn matches_uniform_supersampling_at_lower_cost() {
+        let runner = CountingRunner::new();
+        let num_cells = grid().num_cells();
+
+        let mut uniform_grid = grid();
+        uniform_grid.set_sample_pattern(SamplePattern::Regular(4));
+        let samples = OrbitMapper::map(&LinearMapper::new(100), 64 * 16, 48,
+                                       &runner.run(&uniform_grid).unwrap());
+        let uniform = MappingResult{width: 64, height: 48,
+            values: samples.values.chunks(16).map(average_samples).collect()};
+        assert_eq!(runner.take_count(), num_cells * 16);
+
+        let base = LinearMapper::new(100).map(64, 48, &runner.run(&grid()).unwrap());
+        runner.take_count();
+
+        let adaptive = AdaptiveAntialiaser::new(LinearMapper::new(100), 0.02,
+                                                SamplePattern::Regular(4))
+            .map(&runner, &grid()).unwrap();
+        let cost = runner.take_count();
+
+        // Refined cells average exactly the samples uniform supersampling does.
+        let refined = adaptive.values.iter().zip(base.values.iter())
+            .filter(|&(a, b)| a != b)
+            .count();
+        assert!(refined > 0);
+        assert_eq!(cost, num_cells + 16 * edge_spans(&base.values, 64, 48, 0.02).iter()
+                                             .map(|span| span.width).sum::<usize>());
+        assert!(cost < num_cells * 16 / 2, "adaptive took {} samples", cost);
+
+        let base_error = mean_error(&base, &uniform);
+        let adaptive_error = mean_error(&adaptive, &uniform);
+        assert!(adaptive_error < base_error / 4.0,
+                "adaptive error {} against {} without antialiasing", adaptive_error, base_error);
+    }
+
+    #[test]
+    fn statistics_come_from_the_base_pass() {
+        let runner = CountingRunner::new();
+        let base = HistogramLinearMapper::new(100).map(64, 48, &runner.run(&grid()).unwrap());
+        let edges = edge_spans(&base.values, 64, 48, 0.05);
+
+        let adaptive = AdaptiveAntialiaser::new(HistogramLinearMapper::new(100), 0.05,
+                                                SamplePattern::Regular(4))
+            .map(&runner, &grid()).unwrap();
+        let mut refined = vec![false; 64 * 48];
+        for span in edges.iter() {
+            for x in span.x..span.x + span.width {
+                refined[span.y * 64 + x] = true;
+            }
+        }
+
+        // Cells that weren't refined keep the base pass's equalization.
+        for (index, (a, b)) in adaptive.values.iter().zip(base.values.iter()).enumerate() {
+            if !refined[index] {
+                assert_eq!(a, b);
+            }
+            assert!(value(*a) >= 0.0 && value(*a) <= 1.0);
+        }
+        assert!(refined.iter().any(|r| *r) && !refined.iter().all(|r| *r));
+    }
+}
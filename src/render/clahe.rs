@@ -0,0 +1,224 @@
+use std::cmp;
+
+use rayon::prelude::*;
+
+use fractal::FractalOrbit;
+use super::histogram::Histogram;
+use super::map::{OrbitMapper, MappedCellIntensity, MappingResult};
+use super::map::MappedCellIntensity::{EscapedValue, BoundedValue};
+
+pub const DEFAULT_CLIP_LIMIT: f64 = 3.0;
+pub const DEFAULT_BINS: usize = 256;
+
+// Contrast limited adaptive histogram equalization of an inner mapper's
+// escaped values. The image is split into `tiles_x` by `tiles_y` tiles, each
+// equalized through its own clipped histogram, and every cell blends the
+// mappings of the four tiles whose centers surround it so tile edges don't
+// show. Tiles with fewer than two distinct escaped values are left out of the
+// blend, falling back to the whole image's histogram, and an image with fewer
+// than two is left as it is.
+pub struct ClaheMapper<T> {
+    tiles_x: usize,
+    tiles_y: usize,
+    clip_limit: f64,
+    bins: usize,
+    mapper: T,
+}
+
+impl<T: OrbitMapper> ClaheMapper<T> {
+    pub fn new(tiles_x: usize, tiles_y: usize, mapper: T) -> Self {
+        if tiles_x == 0 || tiles_y == 0 {
+            panic!("CLAHE needs at least one tile in each direction");
+        }
+        ClaheMapper{tiles_x: tiles_x, tiles_y: tiles_y, clip_limit: DEFAULT_CLIP_LIMIT,
+            bins: DEFAULT_BINS, mapper: mapper}
+    }
+
+    // Caps each tile's bins at `clip_limit` times their mean count; lower
+    // limits give gentler contrast.
+    pub fn set_clip_limit(&mut self, clip_limit: f64) {
+        self.clip_limit = clip_limit;
+    }
+
+    pub fn set_bins(&mut self, bins: usize) {
+        if bins == 0 {
+            panic!("CLAHE needs at least one bin");
+        }
+        self.bins = bins;
+    }
+}
+
+fn clipped_histogram<'a, I>(values: I, bins: usize, clip_limit: f64) -> Histogram
+        where I: Iterator<Item=&'a MappedCellIntensity> {
+    let mut histogram = Histogram::new(1.0, bins);
+    for value in values {
+        if let EscapedValue(val) = *value {
+            histogram.add(val);
+        }
+    }
+    histogram.clip(clip_limit);
+    histogram.finish();
+    histogram
+}
+
+// Start of tile `tile` out of `tiles` along a side `len` long.
+fn tile_start(tile: usize, tiles: usize, len: usize) -> usize {
+    tile * len / tiles
+}
+
+// The two tiles whose centers are on either side of cell `pos`, and how far
+// the cell is from the first toward the second.
+fn neighbours(pos: usize, tiles: usize, len: usize) -> (usize, usize, f64) {
+    let tile_len = (len as f64) / (tiles as f64);
+    let between = ((pos as f64) + 0.5) / tile_len - 0.5;
+    let first = cmp::min(between.max(0.0).floor() as usize, tiles - 1);
+    let second = cmp::min(first + 1, tiles - 1);
+    (first, second, (between - (first as f64)).clamp(0.0, 1.0))
+}
+
+impl<T: OrbitMapper> OrbitMapper for ClaheMapper<T> {
+    fn map(&self, width: usize, height: usize, vals: &[FractalOrbit])
+            -> MappingResult {
+        let mut result = self.mapper.map(width, height, vals);
+        let (width, height) = (result.width, result.height);
+        if width == 0 || height == 0 {
+            return result;
+        }
+        let tiles_x = cmp::min(self.tiles_x, width);
+        let tiles_y = cmp::min(self.tiles_y, height);
+
+        let (bins, clip_limit) = (self.bins, self.clip_limit);
+        let global = clipped_histogram(result.values.iter(), bins, clip_limit);
+        let tiles: Vec<Histogram> = {
+            let values = &result.values;
+            (0..tiles_x * tiles_y).into_par_iter()
+                .map(|tile| {
+                    let (tx, ty) = (tile % tiles_x, tile / tiles_x);
+                    let columns = tile_start(tx, tiles_x, width)..tile_start(tx + 1, tiles_x, width);
+                    let rows = tile_start(ty, tiles_y, height)..tile_start(ty + 1, tiles_y, height);
+                    let cells = rows.flat_map(|row| {
+                        values[row * width + columns.start..row * width + columns.end].iter()
+                    });
+                    clipped_histogram(cells, bins, clip_limit)
+                })
+                .collect()
+        };
+
+        result.values.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            let (ty0, ty1, wy) = neighbours(y, tiles_y, height);
+            for (x, value) in row.iter_mut().enumerate() {
+                let val = match *value {
+                    EscapedValue(val) => val,
+                    BoundedValue => continue,
+                };
+                let (tx0, tx1, wx) = neighbours(x, tiles_x, width);
+
+                let mut total = 0.0;
+                let mut weight_sum = 0.0;
+                for &(tx, ty, weight) in [(tx0, ty0, (1.0 - wx) * (1.0 - wy)),
+                                          (tx1, ty0, wx * (1.0 - wy)),
+                                          (tx0, ty1, (1.0 - wx) * wy),
+                                          (tx1, ty1, wx * wy)].iter() {
+                    let tile = &tiles[ty * tiles_x + tx];
+                    if weight > 0.0 && !tile.is_flat() {
+                        total += weight * tile.equalize(val);
+                        weight_sum += weight;
+                    }
+                }
+
+                *value = EscapedValue(if weight_sum > 0.0 {
+                    total / weight_sum
+                } else if !global.is_flat() {
+                    global.equalize(val)
+                } else {
+                    val
+                });
+            }
+        });
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::map::LinearMapper;
+
+    // A bumpy field with a bounded hole in it.
+    fn field(width: usize, height: usize) -> Vec<FractalOrbit> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                if (3..5).contains(&x) && (2..4).contains(&y) {
+                    FractalOrbit::Bounded
+                } else {
+                    FractalOrbit::Escaped(((x * x + 3 * y) % 97) as f64)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn constant_images_are_unchanged() {
+        let vals = vec![FractalOrbit::Escaped(40.0); 12 * 9];
+        let mut vals_with_hole = vals.clone();
+        vals_with_hole[50] = FractalOrbit::Bounded;
+
+        for vals in [vals, vals_with_hole].iter() {
+            let expected = OrbitMapper::map(&LinearMapper::new(100), 12, 9, vals).values;
+            let result = ClaheMapper::new(3, 2, LinearMapper::new(100)).map(12, 9, vals);
+            assert_eq!(result.values, expected);
+        }
+    }
+
+    #[test]
+    fn one_tile_is_plain_equalization() {
+        let vals = field(16, 12);
+        let mapped = OrbitMapper::map(&LinearMapper::new(100), 16, 12, &vals);
+
+        for &clip_limit in [1.0, 2.0, 1e9].iter() {
+            let mut mapper = ClaheMapper::new(1, 1, LinearMapper::new(100));
+            mapper.set_clip_limit(clip_limit);
+            let result = mapper.map(16, 12, &vals);
+
+            let histogram = clipped_histogram(mapped.values.iter(), DEFAULT_BINS, clip_limit);
+            for (value, expected) in result.values.iter().zip(mapped.values.iter()) {
+                match (*value, *expected) {
+                    (EscapedValue(val), EscapedValue(expected)) => {
+                        let expected = histogram.equalize(expected);
+                        assert!((val - expected).abs() < 1e-12,
+                                "clip limit {}: {} != {}", clip_limit, val, expected);
+                    },
+                    (BoundedValue, BoundedValue) => {},
+                    other => panic!("{:?}", other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_need_not_divide_the_image() {
+        let vals = field(13, 7);
+        for &(tiles_x, tiles_y) in [(3, 2), (5, 4), (13, 7), (40, 40)].iter() {
+            let result = ClaheMapper::new(tiles_x, tiles_y, LinearMapper::new(100))
+                .map(13, 7, &vals);
+            assert_eq!((result.width, result.height), (13, 7));
+            for (value, orbit) in result.values.iter().zip(vals.iter()) {
+                match (*value, *orbit) {
+                    (BoundedValue, FractalOrbit::Bounded) => {},
+                    (EscapedValue(val), FractalOrbit::Escaped(_)) =>
+                        assert!(val > 0.0 && val <= 1.0, "{}x{} tiles: {}", tiles_x, tiles_y, val),
+                    other => panic!("{:?}", other),
+                }
+            }
+        }
+
+        // Neighbouring tile starts cover every cell exactly once.
+        let starts: Vec<_> = (0..4).map(|tile| tile_start(tile, 3, 10)).collect();
+        assert_eq!(starts, vec![0, 3, 6, 10]);
+        assert_eq!(neighbours(0, 3, 10), (0, 1, 0.0));
+        let (first, second, _) = neighbours(9, 3, 10);
+        assert_eq!((first, second), (2, 2));
+    }
+}
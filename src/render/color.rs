@@ -0,0 +1,177 @@
+use grid;
+use image;
+use fractal::{FractalOrbit};
+use palette::{Color, Palette};
+use render::{RenderError, FractalRenderer, RenderResult};
+use super::filter::{ReconstructionFilter, DEFAULT_SAMPLE_FILTER};
+use super::map::{OrbitMapper, MappingResult, map_grid};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorFormat {
+    Rgb,
+    Rgba,
+}
+
+// Colors escaped cells through a palette's gradient and bounded cells with
+// its interior color. Grids with several samples per cell are reduced to one
+// value per cell with `filter` before coloring.
+pub struct ColorFractalRenderer<T> {
+    mapper: T,
+    palette: Palette,
+    format: ColorFormat,
+    filter: ReconstructionFilter,
+}
+
+impl<T: OrbitMapper> ColorFractalRenderer<T> {
+    pub fn new(mapper: T, palette: Palette) -> Self {
+        ColorFractalRenderer{mapper: mapper, palette: palette, format: ColorFormat::Rgb,
+            filter: DEFAULT_SAMPLE_FILTER}
+    }
+
+    pub fn set_format(&mut self, format: ColorFormat) {
+        self.format = format;
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn format(&self) -> ColorFormat {
+        self.format
+    }
+}
+
+impl<T: OrbitMapper> FractalRenderer for ColorFractalRenderer<T> {
+    fn render(&self, grid: &grid::Grid, intensities: &[FractalOrbit])
+            -> RenderResult<image::DynamicImage> {
+        let mapped_result = map_grid(&self.mapper, self.filter, grid, intensities)?;
+
+        render_mapped_color(&mapped_result, &self.palette, self.format)
+    }
+}
+
+pub fn render_mapped_color(mapped_result: &MappingResult, palette: &Palette,
+                           format: ColorFormat) -> RenderResult<image::DynamicImage> {
+    if mapped_result.values.len() != mapped_result.width * mapped_result.height {
+        return Err(
+            RenderError::OtherError(
+                "Mapped values don't match the image dimensions".to_string()));
+    }
+
+    let colors: Vec<_> = mapped_result.values.iter()
+        .map(|item| palette.color(*item))
+        .collect();
+    Ok(colors_to_image(mapped_result.width, mapped_result.height, &colors, format))
+}
+
+// Builds an image from one color per pixel, in row order.
+pub fn colors_to_image(width: usize, height: usize, colors: &[Color],
+                       format: ColorFormat) -> image::DynamicImage {
+    let channels = match format {
+        ColorFormat::Rgb => 3,
+        ColorFormat::Rgba => 4,
+    };
+
+    let mut pixels = Vec::with_capacity(colors.len() * channels);
+    for color in colors.iter() {
+        pixels.extend_from_slice(&color.to_rgba8()[..channels]);
+    }
+
+    let (width, height) = (width as u32, height as u32);
+    match format {
+        ColorFormat::Rgb => image::ImageRgb8(
+            image::ImageBuffer::from_raw(width, height, pixels).unwrap()),
+        ColorFormat::Rgba => image::ImageRgba8(
+            image::ImageBuffer::from_raw(width, height, pixels).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImage;
+
+    use palette::GradientStop;
+    use sampling::SamplePattern;
+    use render::map::{AntialiasMapper, LinearMapper};
+    use render::map::MappedCellIntensity::{BoundedValue, EscapedValue};
+    use super::*;
+
+    fn palette() -> Palette {
+        let mut palette = Palette::new(vec![
+            GradientStop::new(0.0, Color::new(1.0, 0.0, 0.0)),
+            GradientStop::new(1.0, Color::with_alpha(0.0, 0.0, 1.0, 0.0))]);
+        palette.set_interior(Color::new(0.0, 1.0, 0.0));
+        palette
+    }
+
+    fn mapped() -> MappingResult {
+        MappingResult{values: vec![EscapedValue(0.0), BoundedValue, EscapedValue(0.5),
+                                   EscapedValue(1.0), BoundedValue, EscapedValue(0.25)],
+                      width: 3, height: 2}
+    }
+
+    #[test]
+    fn mapped_values_color_pixels_in_row_order() {
+        let image = render_mapped_color(&mapped(), &palette(), ColorFormat::Rgba).unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+        let image = image.to_rgba();
+        assert_eq!(image.get_pixel(0, 0).data, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).data, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(2, 0).data, [128, 0, 128, 128]);
+        assert_eq!(image.get_pixel(0, 1).data, [0, 0, 255, 0]);
+        assert_eq!(image.get_pixel(2, 1).data, [191, 0, 64, 191]);
+    }
+
+    #[test]
+    fn rgb_images_drop_alpha() {
+        match render_mapped_color(&mapped(), &palette(), ColorFormat::Rgb).unwrap() {
+            image::ImageRgb8(image) => {
+                assert_eq!(image.get_pixel(2, 0).data, [128, 0, 128]);
+                assert_eq!(image.get_pixel(0, 1).data, [0, 0, 255]);
+            }
+            _ => panic!("expected an RGB image"),
+        }
+    }
+
+    #[test]
+    fn mismatched_sizes_are_errors() {
+        let mut wrong = mapped();
+        wrong.width = 4;
+        assert!(render_mapped_color(&wrong, &palette(), ColorFormat::Rgb).is_err());
+
+        let renderer = ColorFractalRenderer::new(LinearMapper::new(10), palette());
+        let grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 3, 2);
+        assert!(renderer.render(&grid, &[FractalOrbit::Bounded; 5]).is_err());
+        let image = renderer.render(&grid, &[FractalOrbit::Bounded; 6]).unwrap();
+        assert_eq!(image.to_rgb().get_pixel(2, 1).data, [0, 255, 0]);
+    }
+
+    #[test]
+    fn multi_sample_grids_render_a_pixel_per_cell() {
+        let cells: Vec<_> = (0..24)
+            .map(|i| if i % 5 == 0 {
+                FractalOrbit::Bounded
+            } else {
+                FractalOrbit::Escaped((i % 11) as f64 + 0.3)
+            })
+            .collect();
+        let samples: Vec<_> = cells.iter().flat_map(|cell| vec![*cell; 3]).collect();
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 6, 4);
+        let renderer = ColorFractalRenderer::new(LinearMapper::new(11), palette());
+        let expected = renderer.render(&grid, &cells).unwrap().to_rgb().into_raw();
+
+        grid.set_sample_pattern(SamplePattern::Jittered(3));
+        let image = renderer.render(&grid, &samples).unwrap().to_rgb().into_raw();
+        assert_eq!(image.len(), expected.len());
+        assert!(image.iter().zip(expected.iter()).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1),
+                "{:?} != {:?}", image, expected);
+
+        let aa = ColorFractalRenderer::new(AntialiasMapper::new(1, LinearMapper::new(11)),
+                                           palette());
+        assert!(aa.render(&grid, &samples).is_err());
+    }
+}
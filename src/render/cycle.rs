@@ -0,0 +1,228 @@
+use std::io::Write;
+
+use gif;
+use gif::SetParameter;
+use grid;
+use image;
+use fractal::{FractalOrbit};
+use palette::{Palette};
+use render::{RenderError, RenderResult};
+use super::color::{ColorFormat, render_mapped_color};
+use super::filter::{ReconstructionFilter, DEFAULT_SAMPLE_FILTER};
+use super::map::{OrbitMapper, MappedCellIntensity, MappingResult, map_grid};
+
+// GIF frames index a 256 color table: escaped cells use the first
+// GIF_LEVELS entries and bounded cells the last one.
+const GIF_LEVELS: usize = 255;
+const GIF_INTERIOR_INDEX: u8 = 255;
+
+// Classic color cycling: the orbits are mapped once and every frame recolors
+// them with the palette's offset advanced a little further, so the gradient
+// flows through the image and the last frame leads back into the first.
+pub struct PaletteCycler<M> {
+    mapper: M,
+    palette: Palette,
+    num_frames: usize,
+    // In hundredths of a second, the unit GIF uses.
+    frame_delay: u16,
+    // Reduces grids with several samples per cell to one value per cell.
+    filter: ReconstructionFilter,
+}
+
+impl<M: OrbitMapper> PaletteCycler<M> {
+    pub fn new(mapper: M, palette: Palette, num_frames: usize) -> Self {
+        if num_frames == 0 {
+            panic!("Palette cycling needs at least one frame");
+        }
+        PaletteCycler{mapper: mapper, palette: palette, num_frames: num_frames, frame_delay: 4,
+            filter: DEFAULT_SAMPLE_FILTER}
+    }
+
+    pub fn set_frame_delay(&mut self, frame_delay: u16) {
+        self.frame_delay = frame_delay;
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    pub fn frame_delay(&self) -> u16 {
+        self.frame_delay
+    }
+
+    pub fn map(&self, grid: &grid::Grid, orbits: &[FractalOrbit]) -> RenderResult<MappingResult> {
+        map_grid(&self.mapper, self.filter, grid, orbits)
+    }
+
+    // The palette as it stands in `frame`. Over all the frames the offset
+    // advances by one whole gradient.
+    pub fn frame_palette(&self, frame: usize) -> Palette {
+        let mut palette = self.palette.clone();
+        let shift = (frame % self.num_frames) as f64 / (self.num_frames as f64);
+        palette.set_offset(self.palette.offset() + shift);
+        palette
+    }
+
+    pub fn render_frame(&self, mapped: &MappingResult, frame: usize)
+            -> RenderResult<image::DynamicImage> {
+        render_mapped_color(mapped, &self.frame_palette(frame), ColorFormat::Rgb)
+    }
+
+    // Writes every frame as a looping GIF. Escaped cells are quantized to
+    // GIF_LEVELS intensities once, and each frame only swaps in its own color
+    // table. GIF has no partial transparency, so the palette's alpha is
+    // dropped.
+    pub fn write_gif<W: Write>(&self, w: W, mapped: &MappingResult) -> RenderResult<()> {
+        let (width, height) = (mapped.width, mapped.height);
+        if mapped.values.len() != width * height {
+            return Err(
+                RenderError::OtherError(
+                    "Mapped values don't match the image dimensions".to_string()));
+        }
+        if width > (u16::MAX as usize) || height > (u16::MAX as usize) {
+            return Err(
+                RenderError::OtherError("Image is too large for a GIF".to_string()));
+        }
+
+        let indices: Vec<u8> = mapped.values.iter()
+            .map(|value| match *value {
+                MappedCellIntensity::BoundedValue => GIF_INTERIOR_INDEX,
+                MappedCellIntensity::EscapedValue(val) =>
+                    (val.clamp(0.0, 1.0) * ((GIF_LEVELS - 1) as f64)).round() as u8,
+            })
+            .collect();
+
+        let mut encoder = gif::Encoder::new(w, width as u16, height as u16, &[])?;
+        encoder.set(gif::Repeat::Infinite)?;
+        for frame in 0..self.num_frames {
+            let gif_frame = gif::Frame{
+                width: width as u16,
+                height: height as u16,
+                delay: self.frame_delay,
+                palette: Some(self.color_table(frame)),
+                buffer: indices.as_slice().into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&gif_frame)?;
+        }
+        Ok(())
+    }
+
+    fn color_table(&self, frame: usize) -> Vec<u8> {
+        let palette = self.frame_palette(frame);
+        let mut table = Vec::with_capacity(3 * (GIF_LEVELS + 1));
+        for level in 0..GIF_LEVELS {
+            let val = (level as f64) / ((GIF_LEVELS - 1) as f64);
+            table.extend_from_slice(&palette.color_at(val).to_rgba8()[..3]);
+        }
+        table.extend_from_slice(&palette.interior().to_rgba8()[..3]);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::{Color, GradientStop};
+    use sampling::SamplePattern;
+    use render::map::{AntialiasMapper, LinearMapper};
+    use super::*;
+
+    fn cycler(num_frames: usize) -> PaletteCycler<LinearMapper> {
+        let mut palette = Palette::new(vec![
+            GradientStop::new(0.0, Color::new(1.0, 0.0, 0.0)),
+            GradientStop::new(0.5, Color::new(0.0, 1.0, 0.0)),
+            GradientStop::new(1.0, Color::new(1.0, 0.0, 0.0))]);
+        palette.set_interior(Color::new(0.0, 0.0, 1.0));
+        palette.set_offset(0.1);
+        PaletteCycler::new(LinearMapper::new(10), palette, num_frames)
+    }
+
+    fn assert_same_color(a: Color, b: Color) {
+        assert!((a.r - b.r).abs() < 1e-9 && (a.g - b.g).abs() < 1e-9 && (a.b - b.b).abs() < 1e-9,
+                "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn gifs_have_a_frame_per_step() {
+        let cycler = cycler(6);
+        let grid = grid::Grid::new(-1.0, 1.0, 1.0, -1.0, 5, 3);
+        let mut orbits: Vec<_> = (0..15).map(|i| FractalOrbit::Escaped((i % 11) as f64)).collect();
+        orbits[7] = FractalOrbit::Bounded;
+        let mapped = cycler.map(&grid, &orbits).unwrap();
+
+        let mut gif_data = Vec::new();
+        cycler.write_gif(&mut gif_data, &mapped).unwrap();
+        assert!(gif_data.windows(11).any(|window| window == b"NETSCAPE2.0"));
+
+        let mut decoder = gif::Decoder::new(&gif_data[..]);
+        decoder.set(gif::ColorOutput::Indexed);
+        let mut reader = decoder.read_info().unwrap();
+        let mut frames = 0;
+        while let Some(frame) = reader.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height), (5, 3));
+            assert_eq!(frame.delay, cycler.frame_delay());
+            assert_eq!(frame.palette, Some(cycler.color_table(frames)));
+            assert_eq!(frame.buffer[7], GIF_INTERIOR_INDEX);
+            assert_eq!(frame.buffer[0], 0);
+            assert_eq!(frame.buffer[10], GIF_LEVELS as u8 - 1);
+            frames += 1;
+        }
+        assert_eq!(frames, 6);
+    }
+
+    #[test]
+    fn the_cycle_loops_back_to_its_first_frame() {
+        let cycler = cycler(8);
+        let step = 1.0 / 8.0;
+        assert_eq!(cycler.frame_palette(8), cycler.frame_palette(0));
+        assert_eq!(cycler.frame_palette(0), *cycler.palette());
+
+        // Every frame, the last one into the first included, moves the
+        // gradient on by the same step.
+        for frame in 0..8 {
+            let (current, next) = (cycler.frame_palette(frame), cycler.frame_palette(frame + 1));
+            for i in 0..20 {
+                let val = (i as f64) / 20.0;
+                assert_same_color(next.color_at(val), current.color_at(val + step));
+            }
+        }
+    }
+
+    #[test]
+    fn multi_sample_grids_map_a_value_per_cell() {
+        let cells: Vec<_> = (0..24)
+            .map(|i| if i % 5 == 0 {
+                FractalOrbit::Bounded
+            } else {
+                FractalOrbit::Escaped((i % 11) as f64 + 0.3)
+            })
+            .collect();
+        let samples: Vec<_> = cells.iter().flat_map(|cell| vec![*cell; 3]).collect();
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 6, 4);
+        let cycler = cycler(4);
+        let expected = cycler.map(&grid, &cells).unwrap();
+
+        grid.set_sample_pattern(SamplePattern::Jittered(3));
+        let mapped = cycler.map(&grid, &samples).unwrap();
+        assert_eq!((mapped.width, mapped.height), (6, 4));
+        for (value, expected) in mapped.values.iter().zip(expected.values.iter()) {
+            match (*value, *expected) {
+                (MappedCellIntensity::EscapedValue(a), MappedCellIntensity::EscapedValue(b)) =>
+                    assert!((a - b).abs() < 1e-12, "{} != {}", a, b),
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+
+        let aa = PaletteCycler::new(AntialiasMapper::new(1, LinearMapper::new(11)),
+                                    cycler.palette().clone(), 4);
+        assert!(aa.map(&grid, &samples).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::f64::consts::PI;
+
+use image;
+use num_complex::{Complex};
+use fractal::{Fractal};
+use grid;
+use plane::{PlaneTransform, TransformedFractal};
+use runner::{FractalRunner};
+use render::{RenderError, RenderResult};
+use view::ViewTransform;
+use super::grayscale::render_mapped;
+use super::map::{OrbitMapper};
+
+// The neighbourhood of `center` on a log-polar grid: angle across, log radius
+// down, from `max_radius` at the top to `min_radius()` at the bottom. Rows are
+// as tall as columns are wide in log-polar terms, so each cell is close to
+// square in the plane and every row shows the same amount of detail.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExponentialMap {
+    center: Complex<f64>,
+    max_radius: f64,
+    angular_cells: usize,
+    radial_cells: usize,
+}
+
+// Renders an exponential map as one grayscale strip. The runner must compute
+// the fractal returned by `ExponentialMap::fractal`.
+pub struct ExponentialMapRenderer<M> {
+    map: ExponentialMap,
+    mapper: M,
+}
+
+impl ExponentialMap {
+    pub fn new(center: Complex<f64>, max_radius: f64, min_radius: f64,
+               angular_cells: usize) -> Self {
+        if angular_cells == 0 {
+            panic!("angular_cells must be at least 1");
+        }
+        if !(min_radius > 0.0 && max_radius > min_radius) {
+            panic!("Radii must satisfy 0 < min_radius < max_radius");
+        }
+        let step = log_step(angular_cells);
+        let radial_cells = ((max_radius / min_radius).ln() / step).ceil() as usize;
+        ExponentialMap{center: center, max_radius: max_radius,
+            angular_cells: angular_cells, radial_cells: radial_cells}
+    }
+
+    #[inline]
+    pub fn center(&self) -> Complex<f64> {
+        self.center
+    }
+
+    #[inline]
+    pub fn max_radius(&self) -> f64 {
+        self.max_radius
+    }
+
+    pub fn min_radius(&self) -> f64 {
+        self.radius_at_row(self.radial_cells as f64)
+    }
+
+    // Distance from the center at row coordinate `row`, where row r covers
+    // [r, r+1).
+    pub fn radius_at_row(&self, row: f64) -> f64 {
+        self.max_radius * (-row * log_step(self.angular_cells)).exp()
+    }
+
+    // A grid over the log-polar plane: the real part of each position is the
+    // log radius and the imaginary part the angle.
+    pub fn grid(&self) -> grid::Grid {
+        let step = log_step(self.angular_cells);
+        let transform = ViewTransform{origin: (self.max_radius.ln(), 0.0),
+            column_step: (0.0, step), row_step: (-step, 0.0)};
+        grid::Grid::from_transform(transform, self.angular_cells, self.radial_cells)
+    }
+
+    pub fn fractal<T: Fractal>(&self, fractal: T) -> TransformedFractal<T> {
+        TransformedFractal::new(fractal, PlaneTransform::Exponential(self.center))
+    }
+}
+
+impl<M: OrbitMapper> ExponentialMapRenderer<M> {
+    pub fn new(map: ExponentialMap, mapper: M) -> Self {
+        ExponentialMapRenderer{map: map, mapper: mapper}
+    }
+
+    pub fn map(&self) -> &ExponentialMap {
+        &self.map
+    }
+
+    pub fn render<R: FractalRunner>(&self, runner: &R) -> RenderResult<image::DynamicImage> {
+        let grid = self.map.grid();
+        let orbits = runner.run(&grid)?;
+        if orbits.len() != grid.num_samples() {
+            return Err(RenderError::OtherError(
+                "Runner output doesn't match the exponential map".to_string()));
+        }
+
+        let mapped = self.mapper.map(grid.cells_wide(), grid.cells_high(), &orbits);
+        render_mapped(&mapped)
+    }
+}
+
+fn log_step(angular_cells: usize) -> f64 {
+    2.0 * PI / (angular_cells as f64)
+}
+
+// Strip depths, in rows, of `num_frames` zoom frames from the shallowest frame
+// whose corners still fall inside the strip to the deepest whose center does.
+// A frame at depth d has its half-width at the radius of strip row d.
+pub fn frame_depths(strip_width: u32, strip_height: u32, frame_width: u32,
+                    frame_height: u32, num_frames: usize) -> RenderResult<Vec<f64>> {
+    if strip_width == 0 || frame_width == 0 || frame_height == 0 {
+        return Err(RenderError::OtherError("Empty strip or frame".to_string()));
+    }
+    let step = log_step(strip_width as usize);
+    let corner = (1.0f64).hypot((frame_height as f64) / (frame_width as f64));
+    let first = corner.ln() / step;
+    let innermost = (frame_width as f64) / 2.0f64.sqrt();
+    let last = (strip_height as f64) - innermost.ln() / step;
+    if last < first {
+        return Err(RenderError::OtherError(
+            "Strip is too short to reconstruct a single frame".to_string()));
+    }
+
+    Ok((0..num_frames)
+        .map(|frame| {
+            if num_frames > 1 {
+                first + (last - first) * (frame as f64) / ((num_frames - 1) as f64)
+            } else {
+                first
+            }
+        })
+        .collect())
+}
+
+// Reconstructs an ordinary view of the plane from an exponential map strip,
+// sampling the strip bilinearly. `depth` is as returned by `frame_depths`.
+pub fn reconstruct_frame(strip: &image::GrayImage, depth: f64, frame_width: u32,
+                         frame_height: u32) -> image::GrayImage {
+    let (strip_width, strip_height) = strip.dimensions();
+    if strip_width == 0 || strip_height == 0 {
+        return image::ImageBuffer::new(frame_width, frame_height);
+    }
+    let step = log_step(strip_width as usize);
+    let half_width = (frame_width as f64) * 0.5;
+
+    image::ImageBuffer::from_fn(frame_width, frame_height, |px, py| {
+        let dx = ((px as f64) + 0.5 - half_width) / half_width;
+        let dy = ((frame_height as f64) * 0.5 - (py as f64) - 0.5) / half_width;
+        let mut angle = dy.atan2(dx);
+        if angle < 0.0 {
+            angle += 2.0 * PI;
+        }
+
+        let column = angle / step - 0.5;
+        let row = depth - dx.hypot(dy).ln() / step - 0.5;
+        image::Luma([sample_bilinear(strip, strip_width, strip_height, column, row)])
+    })
+}
+
+// Columns wrap around, since angle is periodic; rows are clamped.
+fn sample_bilinear(strip: &image::GrayImage, width: u32, height: u32,
+                   column: f64, row: f64) -> u8 {
+    let row = row.clamp(0.0, (height - 1) as f64);
+    let x0 = column.floor();
+    let y0 = row.floor();
+    let fx = column - x0;
+    let fy = row - y0;
+
+    let wrap = |x: f64| (((x as i64) % (width as i64) + width as i64) % width as i64) as u32;
+    let x0i = wrap(x0);
+    let x1i = wrap(x0 + 1.0);
+    let y0i = y0 as u32;
+    let y1i = ((y0 as u32) + 1).min(height - 1);
+
+    let pixel = |x: u32, y: u32| strip.get_pixel(x, y).data[0] as f64;
+    let top = pixel(x0i, y0i) * (1.0 - fx) + pixel(x1i, y0i) * fx;
+    let bottom = pixel(x0i, y1i) * (1.0 - fx) + pixel(x1i, y1i) * fx;
+    (top * (1.0 - fy) + bottom * fy).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The strip row frame pixel (px, py) samples at `depth`.
+    fn pixel_row(depth: f64, strip_width: u32, frame_width: u32, frame_height: u32,
+                 px: u32, py: u32) -> f64 {
+        let half_width = (frame_width as f64) * 0.5;
+        let dx = ((px as f64) + 0.5 - half_width) / half_width;
+        let dy = ((frame_height as f64) * 0.5 - (py as f64) - 0.5) / half_width;
+        depth - dx.hypot(dy).ln() / log_step(strip_width as usize) - 0.5
+    }
+
+    #[test]
+    fn frame_depths_stay_inside_the_strip() {
+        let (strip_width, strip_height, frame_width, frame_height) = (256, 900, 64, 48);
+        let depths = frame_depths(strip_width, strip_height, frame_width, frame_height, 10)
+            .unwrap();
+        assert_eq!(depths.len(), 10);
+        assert!(depths.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // The first frame's corners are within a row of the top of the strip,
+        // and the last frame's innermost pixels reach its bottom.
+        for &(px, py) in [(0, 0), (frame_width - 1, 0), (0, frame_height - 1)].iter() {
+            let corner = pixel_row(depths[0], strip_width, frame_width, frame_height, px, py);
+            assert!((-0.5..0.5).contains(&corner), "{}", corner);
+        }
+        let center = pixel_row(depths[9], strip_width, frame_width, frame_height,
+                               frame_width / 2, frame_height / 2);
+        assert!((center - ((strip_height as f64) - 0.5)).abs() < 1e-9, "{}", center);
+
+        let single = frame_depths(strip_width, strip_height, frame_width, frame_height, 1)
+            .unwrap();
+        assert_eq!(single, vec![depths[0]]);
+        assert!(frame_depths(strip_width, strip_height, frame_width, frame_height, 0)
+                .unwrap().is_empty());
+    }
+
+    #[test]
+    fn short_or_empty_strips_are_errors() {
+        assert!(frame_depths(256, 20, 64, 48, 5).is_err());
+        assert!(frame_depths(0, 900, 64, 48, 5).is_err());
+        assert!(frame_depths(256, 900, 0, 48, 5).is_err());
+    }
+
+    #[test]
+    fn radially_symmetric_strips_reconstruct_circles() {
+        let (strip_width, strip_height, frame_width, frame_height) = (64, 120, 40, 30);
+        let strip = image::ImageBuffer::from_fn(strip_width, strip_height,
+                                                |_, y| image::Luma([(2 * y) as u8]));
+        let depth = frame_depths(strip_width, strip_height, frame_width, frame_height, 3)
+            .unwrap()[1];
+        let frame = reconstruct_frame(&strip, depth, frame_width, frame_height);
+
+        for py in 0..frame_height {
+            for px in 0..frame_width {
+                let row = pixel_row(depth, strip_width, frame_width, frame_height, px, py);
+                let expected = 2.0 * row.clamp(0.0, (strip_height - 1) as f64);
+                let pixel = frame.get_pixel(px, py).data[0] as f64;
+                assert!((pixel - expected).abs() <= 1.0, "({}, {}): {} != {}",
+                        px, py, pixel, expected);
+                // Mirrored across the seam along the positive real axis.
+                assert_eq!(frame.get_pixel(px, frame_height - 1 - py).data[0], pixel as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn columns_wrap_at_the_seam() {
+        let (strip_width, strip_height, frame_width, frame_height) = (64, 120, 40, 30);
+        let brightness = |angle: f64| 128.0 + 100.0 * angle.cos();
+        let step = log_step(strip_width as usize);
+        let strip = image::ImageBuffer::from_fn(strip_width, strip_height, |x, _| {
+            image::Luma([brightness(((x as f64) + 0.5) * step).round() as u8])
+        });
+        let depth = frame_depths(strip_width, strip_height, frame_width, frame_height, 1)
+            .unwrap()[0];
+        let frame = reconstruct_frame(&strip, depth, frame_width, frame_height);
+
+        let half_width = (frame_width as f64) * 0.5;
+        for py in 0..frame_height {
+            for px in 0..frame_width {
+                let dx = (px as f64) + 0.5 - half_width;
+                let dy = (frame_height as f64) * 0.5 - (py as f64) - 0.5;
+                let expected = brightness(dy.atan2(dx));
+                let pixel = frame.get_pixel(px, py).data[0] as f64;
+                assert!((pixel - expected).abs() <= 1.5, "({}, {}): {} != {}",
+                        px, py, pixel, expected);
+            }
+        }
+    }
+}
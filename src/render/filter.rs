@@ -0,0 +1,212 @@
+use std::f64::consts::PI;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ReconstructionFilter {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    MitchellNetravali,
+    Lanczos,
+}
+
+// Mitchell-Netravali with B = C = 1/3, the parameters recommended by the
+// original paper.
+const MITCHELL_B: f64 = 1.0 / 3.0;
+const MITCHELL_C: f64 = 1.0 / 3.0;
+
+const GAUSSIAN_SIGMA: f64 = 0.5;
+const LANCZOS_LOBES: f64 = 3.0;
+
+const WEIGHT_EPSILON: f64 = 1e-12;
+
+// The filter renderers reduce a grid's samples to cells with by default, so
+// samples near a cell's center count for more than those near its edges.
+pub const DEFAULT_SAMPLE_FILTER: ReconstructionFilter = ReconstructionFilter::Gaussian;
+
+// The input cells contributing to one output pixel, starting at `start`, and
+// their normalized weights.
+pub struct FilterTaps {
+    pub start: usize,
+    pub weights: Vec<f64>,
+}
+
+impl ReconstructionFilter {
+    // Half-width of the filter's support, in output pixels.
+    pub fn radius(&self) -> f64 {
+        match *self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent => 1.0,
+            ReconstructionFilter::Gaussian => 3.0 * GAUSSIAN_SIGMA,
+            ReconstructionFilter::MitchellNetravali => 2.0,
+            ReconstructionFilter::Lanczos => LANCZOS_LOBES,
+        }
+    }
+
+    // Weight of a sample `x` output pixels away from the pixel center.
+    pub fn weight(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x >= self.radius() {
+            return 0.0;
+        }
+
+        match *self {
+            ReconstructionFilter::Box => 1.0,
+            ReconstructionFilter::Tent => 1.0 - x,
+            ReconstructionFilter::Gaussian =>
+                (-x*x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp(),
+            ReconstructionFilter::MitchellNetravali => mitchell(x),
+            ReconstructionFilter::Lanczos => sinc(x) * sinc(x / LANCZOS_LOBES),
+        }
+    }
+
+    // Taps for each of the `out_len` output pixels when reducing `in_len`
+    // cells by `factor`. Taps are clipped to the input and renormalized, so
+    // pixels at the edges, including a last pixel only partly covered by
+    // input, are still weighted correctly.
+    pub fn taps(&self, in_len: usize, factor: usize, out_len: usize) -> Vec<FilterTaps> {
+        let scale = factor as f64;
+        let reach = (self.radius() * scale).ceil() as isize;
+
+        (0..out_len)
+            .map(|pixel| {
+                let center = ((pixel as f64) + 0.5) * scale;
+                let first = ((center as isize) - reach).max(0) as usize;
+                let last = ((center as isize) + reach).min(in_len as isize) as usize;
+
+                // Lanczos zero crossings come out as tiny nonzero weights;
+                // snap them so they don't mix in neighbouring cells.
+                let mut weights: Vec<_> = (first..last)
+                    .map(|cell| self.weight(((cell as f64) + 0.5 - center) / scale))
+                    .map(|weight| if weight.abs() < WEIGHT_EPSILON { 0.0 } else { weight })
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                if total != 0.0 {
+                    for weight in weights.iter_mut() {
+                        *weight /= total;
+                    }
+                }
+                FilterTaps{start: first, weights: weights}
+            })
+            .collect()
+    }
+
+    // Normalized weights of samples at `offsets` within one cell, as given by
+    // `SamplePattern::offsets`, by their distance from the cell's center. If
+    // the filter gives none of them any weight they count equally.
+    pub fn sample_weights(&self, offsets: &[(f64, f64)]) -> Vec<f64> {
+        let mut weights: Vec<_> = offsets.iter()
+            .map(|&(u, v)| self.weight(u - 0.5) * self.weight(v - 0.5))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight = if total > 0.0 { *weight / total } else { 1.0 / (offsets.len() as f64) };
+        }
+        weights
+    }
+}
+
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (MITCHELL_B, MITCHELL_C);
+    let value = if x < 1.0 {
+        (12.0 - 9.0*b - 6.0*c) * x*x*x
+            + (-18.0 + 12.0*b + 6.0*c) * x*x
+            + (6.0 - 2.0*b)
+    } else {
+        (-b - 6.0*c) * x*x*x
+            + (6.0*b + 30.0*c) * x*x
+            + (-12.0*b - 48.0*c) * x
+            + (8.0*b + 24.0*c)
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ReconstructionFilter; 5] = [
+        ReconstructionFilter::Box,
+        ReconstructionFilter::Tent,
+        ReconstructionFilter::Gaussian,
+        ReconstructionFilter::MitchellNetravali,
+        ReconstructionFilter::Lanczos,
+    ];
+
+    #[test]
+    fn weights_vanish_outside_the_radius() {
+        for filter in FILTERS.iter() {
+            let radius = filter.radius();
+            assert!(filter.weight(0.0) > 0.0, "{:?}", filter);
+            assert_eq!(filter.weight(0.3), filter.weight(-0.3), "{:?}", filter);
+            assert_eq!(filter.weight(radius), 0.0, "{:?}", filter);
+            assert_eq!(filter.weight(-radius - 1.0), 0.0, "{:?}", filter);
+        }
+
+        assert_eq!(ReconstructionFilter::Tent.weight(0.25), 0.75);
+        assert!((ReconstructionFilter::MitchellNetravali.weight(0.0) - 8.0 / 9.0).abs() < 1e-12);
+        assert!(ReconstructionFilter::Lanczos.weight(1.0).abs() < 1e-12);
+        assert!(ReconstructionFilter::Lanczos.weight(1.5) < 0.0);
+    }
+
+    #[test]
+    fn taps_are_normalized_up_to_the_edges() {
+        for filter in FILTERS.iter() {
+            for &(in_len, factor) in [(12usize, 2), (10, 3), (7, 7), (5, 1)].iter() {
+                let out_len = in_len.div_ceil(factor);
+                let taps = filter.taps(in_len, factor, out_len);
+                assert_eq!(taps.len(), out_len);
+                for tap in taps.iter() {
+                    assert!(tap.start + tap.weights.len() <= in_len, "{:?}", filter);
+                    let total: f64 = tap.weights.iter().sum();
+                    assert!((total - 1.0).abs() < 1e-12, "{:?} sums to {}", filter, total);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_taps_cover_one_block() {
+        let taps = ReconstructionFilter::Box.taps(10, 3, 4);
+        let blocks: Vec<Vec<(usize, f64)>> = taps.iter()
+            .map(|tap| tap.weights.iter().enumerate()
+                 .filter(|&(_, weight)| *weight != 0.0)
+                 .map(|(i, weight)| (tap.start + i, *weight))
+                 .collect())
+            .collect();
+        let third = 1.0 / 3.0;
+        assert_eq!(blocks, vec![vec![(0, third), (1, third), (2, third)],
+                                vec![(3, third), (4, third), (5, third)],
+                                vec![(6, third), (7, third), (8, third)],
+                                vec![(9, 1.0)]]);
+    }
+
+    #[test]
+    fn sample_weights_favour_the_cell_center() {
+        let offsets = [(0.5, 0.5), (0.25, 0.5), (0.1, 0.9), (0.75, 0.25)];
+        for filter in FILTERS.iter() {
+            let weights = filter.sample_weights(&offsets);
+            let total: f64 = weights.iter().sum();
+            assert!((total - 1.0).abs() < 1e-12, "{:?} sums to {}", filter, total);
+            assert!(weights[0] >= weights[1] && weights[1] >= weights[3], "{:?}", filter);
+            assert!(weights[3] >= weights[2], "{:?}", filter);
+        }
+
+        let quarters = ReconstructionFilter::Box.sample_weights(&offsets);
+        assert_eq!(quarters, vec![0.25; 4]);
+        let tent = ReconstructionFilter::Tent.sample_weights(&[(0.5, 0.5), (0.0, 0.5)]);
+        assert!((tent[0] - 2.0 / 3.0).abs() < 1e-12 && (tent[1] - 1.0 / 3.0).abs() < 1e-12);
+
+        // A box gives samples on the cell's edge no weight, so they count alike.
+        assert_eq!(ReconstructionFilter::Box.sample_weights(&[(0.0, 0.0), (0.0, 0.5)]),
+                   vec![0.5, 0.5]);
+    }
+}
@@ -3,48 +3,92 @@ use grid;
 use image;
 use fractal::{FractalOrbit};
 use render::{RenderError, FractalRenderer, RenderResult};
-use super::map::{OrbitMapper, MappedCellIntensity};
+use super::filter::{ReconstructionFilter, DEFAULT_SAMPLE_FILTER};
+use super::map::{OrbitMapper, MappedCellIntensity, MappingResult, map_grid};
 
+// Grids with several samples per cell are reduced to one value per cell with
+// `filter`.
 pub struct GrayscaleFractalRenderer<T> {
     mapper: T,
+    filter: ReconstructionFilter,
 }
 
 impl<T: OrbitMapper> GrayscaleFractalRenderer<T> {
     pub fn new(mapper: T) -> Self {
-        GrayscaleFractalRenderer{mapper: mapper}
+        GrayscaleFractalRenderer{mapper: mapper, filter: DEFAULT_SAMPLE_FILTER}
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
+    }
+}
+
+pub fn intensity_to_luma(intensity: MappedCellIntensity) -> u8 {
+    match intensity {
+        MappedCellIntensity::BoundedValue => 0,
+        MappedCellIntensity::EscapedValue(val) => {
+            let pixel_val = val * (u8::MAX as f64);
+            pixel_val.floor() as u8
+        }
     }
 }
 
 impl<T: OrbitMapper> FractalRenderer for GrayscaleFractalRenderer<T> {
     fn render(&self, grid: &grid::Grid, intensities: &[FractalOrbit]) 
             -> RenderResult<image::DynamicImage> {
+        let mapped_result = map_grid(&self.mapper, self.filter, grid, intensities)?;
 
-        if grid.num_cells() != intensities.len() {
-            return Err(
-                RenderError::OtherError(
-                    "Grid and intensities dimensions don't match".to_string()));
-        }
+        render_mapped(&mapped_result)
+    }
+}
 
-        let mapped_result = self.mapper.map(
-            grid.cells_wide(), grid.cells_high(), intensities);
-        
-        let pixels: Vec<_> = mapped_result.values.iter()
-            .map(|item| {
-                match *item {
-                    MappedCellIntensity::BoundedValue => 0,
-                    MappedCellIntensity::EscapedValue(val) => {
-                        let pixel_val = val * (u8::max_value() as f64);
-                        pixel_val.floor() as u8
-                    }
-                }
+pub fn render_mapped(mapped_result: &MappingResult) -> RenderResult<image::DynamicImage> {
+    if mapped_result.values.len() != mapped_result.width * mapped_result.height {
+        return Err(
+            RenderError::OtherError(
+                "Mapped values don't match the image dimensions".to_string()));
+    }
+
+    let pixels: Vec<_> = mapped_result.values.iter()
+        .map(|item| intensity_to_luma(*item))
+        .collect();
+
+    let buf = image::ImageBuffer::<image::Luma<u8>, _>
+           ::from_raw(mapped_result.width as u32, mapped_result.height as u32,
+                  pixels).unwrap();
+
+    Ok(image::ImageLuma8(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use sampling::SamplePattern;
+    use render::map::{AntialiasMapper, LinearMapper};
+    use super::*;
+
+    #[test]
+    fn multi_sample_grids_render_a_pixel_per_cell() {
+        let cells: Vec<_> = (0..24)
+            .map(|i| if i % 5 == 0 {
+                FractalOrbit::Bounded
+            } else {
+                FractalOrbit::Escaped((i % 11) as f64 + 0.3)
             })
             .collect();
+        let samples: Vec<_> = cells.iter().flat_map(|cell| vec![*cell; 3]).collect();
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 6, 4);
+        let renderer = GrayscaleFractalRenderer::new(LinearMapper::new(11));
+        let expected = renderer.render(&grid, &cells).unwrap().to_luma().into_raw();
 
-        let buf = image::ImageBuffer::<image::Luma<u8>, _>
-               ::from_raw(mapped_result.width as u32, mapped_result.height as u32,
-                      pixels).unwrap();
+        // Every sample of a cell agrees, so the cell keeps its value.
+        grid.set_sample_pattern(SamplePattern::Jittered(3));
+        let image = renderer.render(&grid, &samples).unwrap().to_luma().into_raw();
+        assert_eq!(image.len(), expected.len());
+        assert!(image.iter().zip(expected.iter()).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1),
+                "{:?} != {:?}", image, expected);
 
-        Ok(image::ImageLuma8(buf))
+        let aa = GrayscaleFractalRenderer::new(AntialiasMapper::new(1, LinearMapper::new(11)));
+        assert!(aa.render(&grid, &samples).is_err());
+        assert!(renderer.render(&grid, &cells).is_err());
     }
 }
-
@@ -0,0 +1,257 @@
+use std::cmp;
+
+// How much each value counts once the histogram is collected. Damping the
+// counts of crowded bins weakens equalization, which otherwise spends most of
+// the tonal range on the few escape times most of the image shares.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HistogramWeighting {
+    Count,
+    Sqrt,
+    Log,
+}
+
+// A histogram of values in [0, range], for mapping values through their
+// cumulative distribution. Each bin's count is spread evenly across the bin,
+// so fractional values get distinct positions in the distribution instead of
+// sharing their bin's.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bins: Vec<f64>,
+    bin_width: f64,
+    cumulative: Vec<f64>,
+    observed: Option<(f64, f64)>,
+}
+
+impl Histogram {
+    pub fn new(range: f64, num_bins: usize) -> Self {
+        if num_bins == 0 {
+            panic!("Histogram needs at least one bin");
+        }
+        if range.is_nan() || range <= 0.0 {
+            panic!("Histogram range must be positive");
+        }
+        Histogram{bins: vec![0.0; num_bins], bin_width: range / (num_bins as f64),
+            cumulative: Vec::new(), observed: None}
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn range(&self) -> f64 {
+        self.bin_width * (self.bins.len() as f64)
+    }
+
+    pub fn total(&self) -> f64 {
+        self.bins.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observed.is_none()
+    }
+
+    // Whether there is no spread of values to equalize.
+    pub fn is_flat(&self) -> bool {
+        match self.observed {
+            Some((low, high)) => low == high,
+            None => true,
+        }
+    }
+
+    // Values outside [0, range] are counted at the nearest end.
+    pub fn add(&mut self, value: f64) {
+        let value = self.clamp(value);
+        let bin = self.bin(value);
+        self.bins[bin] += 1.0;
+        self.observed = Some(match self.observed {
+            Some((low, high)) => (low.min(value), high.max(value)),
+            None => (value, value),
+        });
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.bins.len() != self.bins.len() || other.bin_width != self.bin_width {
+            panic!("Histograms must have the same bins to merge");
+        }
+        for (bin, count) in self.bins.iter_mut().zip(other.bins.iter()) {
+            *bin += *count;
+        }
+        self.observed = match (self.observed, other.observed) {
+            (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+            (a, b) => a.or(b),
+        };
+    }
+
+    pub fn apply_weighting(&mut self, weighting: HistogramWeighting) {
+        for count in self.bins.iter_mut() {
+            *count = match weighting {
+                HistogramWeighting::Count => *count,
+                HistogramWeighting::Sqrt => count.sqrt(),
+                HistogramWeighting::Log => count.ln_1p(),
+            };
+        }
+    }
+
+    // Caps every bin at `limit` times the mean bin count and spreads what was
+    // cut off evenly over all the bins, which bounds how steep the cumulative
+    // distribution, and so the contrast, can get.
+    pub fn clip(&mut self, limit: f64) {
+        let cap = limit * self.total() / (self.bins.len() as f64);
+        let mut excess = 0.0;
+        for count in self.bins.iter_mut() {
+            if *count > cap {
+                excess += *count - cap;
+                *count = cap;
+            }
+        }
+        let share = excess / (self.bins.len() as f64);
+        for count in self.bins.iter_mut() {
+            *count += share;
+        }
+    }
+
+    // Builds the cumulative distribution. Call this after adding, weighting
+    // and clipping, and before `cdf` or `equalize`.
+    pub fn finish(&mut self) {
+        let mut running = 0.0;
+        self.cumulative = Some(0.0).into_iter()
+            .chain(self.bins.iter().map(|count| {
+                running += *count;
+                running
+            }))
+            .collect();
+    }
+
+    // Fraction of the histogram below `value`.
+    pub fn cdf(&self, value: f64) -> f64 {
+        let total = match self.cumulative.last() {
+            Some(total) if *total > 0.0 => *total,
+            _ => return 0.0,
+        };
+        let value = self.clamp(value);
+        let bin = self.bin(value);
+        let within = (value / self.bin_width - (bin as f64)).clamp(0.0, 1.0);
+        (self.cumulative[bin] + within * self.bins[bin]) / total
+    }
+
+    // `value`'s position in the distribution, stretched so that the highest
+    // value added maps to 1. Like escape times, which never reach 0, the
+    // lowest value maps to the share of the observed values in its bin.
+    pub fn equalize(&self, value: f64) -> f64 {
+        let (low, high) = match self.observed {
+            Some(observed) => observed,
+            None => return 1.0,
+        };
+        let bottom = self.cdf(low);
+        let span = self.cdf(high) - bottom;
+        if span <= 0.0 {
+            return 1.0;
+        }
+
+        let (low_bin, high_bin) = (self.bin(low), self.bin(high));
+        let observed = self.cumulative[high_bin + 1] - self.cumulative[low_bin];
+        let floor = self.bins[low_bin] / observed;
+        let position = ((self.cdf(value) - bottom) / span).clamp(0.0, 1.0);
+        floor + (1.0 - floor) * position
+    }
+
+    fn clamp(&self, value: f64) -> f64 {
+        value.max(0.0).min(self.range())
+    }
+
+    fn bin(&self, value: f64) -> usize {
+        cmp::min((value / self.bin_width) as usize, self.bins.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(values: &[(f64, usize)]) -> Histogram {
+        let mut histogram = Histogram::new(10.0, 10);
+        for &(value, count) in values.iter() {
+            for _ in 0..count {
+                histogram.add(value);
+            }
+        }
+        histogram
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn fractional_values_get_distinct_positions() {
+        let mut histogram = filled(&[(1.0, 1), (1.5, 1), (3.0, 2)]);
+        histogram.finish();
+        assert_close(histogram.cdf(1.0), 0.0);
+        assert_close(histogram.cdf(1.25), 0.125);
+        assert_close(histogram.cdf(1.5), 0.25);
+        assert_close(histogram.cdf(2.0), 0.5);
+        assert_close(histogram.cdf(3.0), 0.5);
+        assert_close(histogram.cdf(20.0), 1.0);
+        assert_close(histogram.cdf(-1.0), 0.0);
+    }
+
+    #[test]
+    fn equalized_values_are_above_zero() {
+        let mut histogram = filled(&[(1.0, 1), (2.0, 1), (3.0, 1)]);
+        histogram.finish();
+        assert_close(histogram.equalize(1.0), 1.0 / 3.0);
+        assert_close(histogram.equalize(2.0), 2.0 / 3.0);
+        assert_close(histogram.equalize(3.0), 1.0);
+        assert_close(histogram.equalize(0.0), 1.0 / 3.0);
+        assert_close(histogram.equalize(9.0), 1.0);
+
+        let mut single = filled(&[(4.0, 3)]);
+        single.finish();
+        assert_close(single.equalize(4.0), 1.0);
+
+        let mut empty = Histogram::new(10.0, 10);
+        empty.finish();
+        assert!(empty.is_empty());
+        assert_close(empty.equalize(4.0), 1.0);
+    }
+
+    #[test]
+    fn weighting_damps_crowded_bins() {
+        let equalized = |weighting| {
+            let mut histogram = filled(&[(0.0, 99), (5.0, 1)]);
+            histogram.apply_weighting(weighting);
+            histogram.finish();
+            assert_close(histogram.equalize(5.0), 1.0);
+            histogram.equalize(0.0)
+        };
+
+        assert_close(equalized(HistogramWeighting::Count), 0.99);
+        assert_close(equalized(HistogramWeighting::Sqrt), 99f64.sqrt() / (99f64.sqrt() + 1.0));
+        assert_close(equalized(HistogramWeighting::Log), 100f64.ln() / (100f64.ln() + 2f64.ln()));
+    }
+
+    #[test]
+    fn clipping_spreads_the_excess() {
+        let mut values = vec![(0.5, 91)];
+        values.extend((1..10).map(|bin| ((bin as f64) + 0.5, 1)));
+        let mut histogram = filled(&values);
+
+        // The mean bin holds 10, so a limit of 2 caps bins at 20 and spreads
+        // the other 71 over all 10 bins.
+        histogram.clip(2.0);
+        histogram.finish();
+        assert_close(histogram.total(), 100.0);
+        assert_close(histogram.cdf(1.0), 0.271);
+        assert_close(histogram.cdf(2.0), 0.352);
+
+        let mut unclipped = filled(&values);
+        unclipped.finish();
+        assert!(histogram.equalize(0.5) < unclipped.equalize(0.5));
+    }
+
+    #[test]
+    #[should_panic(expected = "range must be positive")]
+    fn nan_ranges_are_rejected() {
+        Histogram::new(f64::NAN, 10);
+    }
+}
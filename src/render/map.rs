@@ -1,7 +1,36 @@
+use std::cmp::{self, Ordering};
+
+use rayon::prelude::*;
+
+use grid;
 use fractal::FractalOrbit;
+use render::{RenderError, RenderResult};
+use super::filter::ReconstructionFilter;
+use super::histogram::{Histogram, HistogramWeighting};
 
 pub trait OrbitMapper {
     fn map(&self, width: usize, height: usize, vals: &[FractalOrbit]) -> MappingResult; 
+
+    // Whether every value is mapped on its own, using at most statistics over
+    // all of them, so a grid's samples can be mapped before they're reduced to
+    // cells. Mappers that combine neighbouring cells can't be given samples.
+    fn maps_samples(&self) -> bool {
+        false
+    }
+}
+
+pub trait RowMapper {
+    type Statistics;
+
+    fn new_statistics(&self) -> Self::Statistics;
+    fn map_row(&self, stats: &Self::Statistics, vals: &[FractalOrbit])
+        -> Vec<MappedCellIntensity>;
+
+    fn needs_statistics(&self) -> bool {
+        false
+    }
+    fn accumulate(&self, _stats: &mut Self::Statistics, _vals: &[FractalOrbit]) {}
+    fn finish_statistics(&self, _stats: &mut Self::Statistics) {}
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -22,8 +51,17 @@ pub struct LinearMapper {
     max_iter: usize,
 }
 
+// Histogram equalization over the escaped points only, so a large interior
+// doesn't keep the exterior from reaching full brightness.
 pub struct HistogramLinearMapper {
     max_iter: usize,
+    bins_per_iteration: usize,
+    weighting: HistogramWeighting,
+    clip_limit: Option<f64>,
+}
+
+pub struct HistogramStatistics {
+    histogram: Histogram,
 }
 
 pub struct LogarithmicMapper {
@@ -32,10 +70,51 @@ pub struct LogarithmicMapper {
 }
 
 pub struct AntialiasMapper<T> {
-    aa_level: u32,
+    factor: usize,
+    filter: ReconstructionFilter,
     mapper: T,
 }
 
+// Applies `transform` to every escaped value of an inner mapper. Adapters
+// nest, so `TransformMapper::new(TransformMapper::new(m, Gamma(0.5)), Invert)`
+// applies the gamma first.
+pub struct TransformMapper<T, X> {
+    mapper: T,
+    transform: X,
+}
+
+// Stretches the escaped values of an inner mapper so that the `low` to `high`
+// percentile range of the image covers [0, 1], clamping the tails.
+pub struct PercentileClampMapper<T> {
+    low: f64,
+    high: f64,
+    mapper: T,
+}
+
+// A function on escaped values in [0, 1]. Any `Fn(f64) -> f64` closure is one.
+pub trait ValueTransform {
+    fn apply(&self, val: f64) -> f64;
+}
+
+// Raises values to the given power; below 1 brightens, above 1 darkens.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gamma(pub f64);
+
+// Scales values about the midpoint by `contrast`, then adds `brightness`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Contrast {
+    pub contrast: f64,
+    pub brightness: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Invert;
+
+// Runs through [0, 1] the given number of times, so a palette or gradient
+// repeats across the value range.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Repeat(pub f64);
+
 impl LinearMapper {
     pub fn new(max_iter: usize) -> Self {
         LinearMapper{max_iter: max_iter}
@@ -53,56 +132,106 @@ impl LinearMapper {
 impl OrbitMapper for LinearMapper {
     fn map(&self, width: usize, height: usize, vals: &[FractalOrbit]) 
             -> MappingResult {
-        let vals = vals.iter()
+        let vals = vals.par_iter()
             .map(|o| self.map(*o))
             .collect();
         MappingResult{values: vals, width: width, height: height}
     }
+
+    fn maps_samples(&self) -> bool {
+        true
+    }
+}
+
+impl RowMapper for LinearMapper {
+    type Statistics = ();
+
+    fn new_statistics(&self) {}
+
+    fn map_row(&self, _stats: &(), vals: &[FractalOrbit]) -> Vec<MappedCellIntensity> {
+        vals.iter().map(|o| self.map(*o)).collect()
+    }
 }
 
 impl HistogramLinearMapper {
     pub fn new(max_iter: usize) -> Self {
-        let mut hist = Vec::with_capacity(max_iter);
-        hist.resize(max_iter, 0);
+        HistogramLinearMapper{max_iter: max_iter, bins_per_iteration: 1,
+            weighting: HistogramWeighting::Count, clip_limit: None}
+    }
 
-        HistogramLinearMapper{max_iter: max_iter}
+    pub fn set_bins_per_iteration(&mut self, bins_per_iteration: usize) {
+        if bins_per_iteration == 0 {
+            panic!("bins_per_iteration must be at least 1");
+        }
+        self.bins_per_iteration = bins_per_iteration;
+    }
+
+    pub fn set_weighting(&mut self, weighting: HistogramWeighting) {
+        self.weighting = weighting;
+    }
+
+    // Caps each bin at `limit` times the mean bin count; see `Histogram::clip`.
+    pub fn set_clip_limit(&mut self, clip_limit: Option<f64>) {
+        self.clip_limit = clip_limit;
     }
 }
 
 impl OrbitMapper for HistogramLinearMapper {
     fn map(&self, width: usize, height: usize, vals: &[FractalOrbit]) 
             -> MappingResult {
-        let mut histogram = vec![0; self.max_iter+1];
+        let mut stats = self.new_statistics();
+        self.accumulate(&mut stats, vals);
+        self.finish_statistics(&mut stats);
 
+        let output = self.map_row(&stats, vals);
+
+        MappingResult{values: output, width: width, height: height}
+    }
+
+    fn maps_samples(&self) -> bool {
+        true
+    }
+}
+
+impl RowMapper for HistogramLinearMapper {
+    type Statistics = HistogramStatistics;
+
+    fn new_statistics(&self) -> HistogramStatistics {
+        let range = cmp::max(self.max_iter, 1);
+        HistogramStatistics{
+            histogram: Histogram::new(range as f64, range * self.bins_per_iteration)}
+    }
+
+    fn needs_statistics(&self) -> bool {
+        true
+    }
+
+    fn accumulate(&self, stats: &mut HistogramStatistics, vals: &[FractalOrbit]) {
         for orbit in vals.iter() {
-            match *orbit {
-                FractalOrbit::Bounded => {},
-                FractalOrbit::Escaped(val) => histogram[val.floor() as usize] += 1,
+            if let FractalOrbit::Escaped(val) = *orbit {
+                stats.histogram.add(val);
             }
         }
+    }
 
-        let total = vals.len() as f64;
-        let mapping: Vec<_> = histogram.iter()
-            .scan(0.0, |state, item| {
-                *state += *item as f64;
-                Some(*state / total)
-            })
-            .collect();
+    fn finish_statistics(&self, stats: &mut HistogramStatistics) {
+        stats.histogram.apply_weighting(self.weighting);
+        if let Some(limit) = self.clip_limit {
+            stats.histogram.clip(limit);
+        }
+        stats.histogram.finish();
+    }
 
-        let output: Vec<_> = vals.iter()
+    fn map_row(&self, stats: &HistogramStatistics, vals: &[FractalOrbit])
+            -> Vec<MappedCellIntensity> {
+        vals.iter()
             .map(|item| {
                 match *item {
                     FractalOrbit::Bounded => BoundedValue,
-                    FractalOrbit::Escaped(val) => {
-                        let trunc = val.floor();
-                        let bin_val = mapping[trunc as usize];
-                        EscapedValue(bin_val)
-                    }
+                    FractalOrbit::Escaped(val) => EscapedValue(stats.histogram.equalize(val)),
                 }
             })
-            .collect();
-
-        MappingResult{values: output, width: width, height: height}
+            .collect()
     }
 }
 
@@ -110,44 +239,71 @@ impl LogarithmicMapper {
     pub fn new(max_iter: usize, strength: f64) -> Self {
         LogarithmicMapper{max_iter: max_iter, strength: strength}
     }
+
+    fn map(&self, val: FractalOrbit) -> MappedCellIntensity {
+        match val {
+            FractalOrbit::Bounded => BoundedValue,
+            FractalOrbit::Escaped(val) => {
+                let multiplier = self.strength + 1.0;
+                let scaled_val = val / (self.max_iter as f64);
+                let mapped_val = f64::log10(scaled_val * multiplier + 1.0) 
+                    / f64::log10(multiplier + 1.0);
+                EscapedValue(mapped_val)
+            }
+        }
+    }
 }
 
 impl OrbitMapper for LogarithmicMapper {
     fn map(&self, width: usize, height: usize, vals: &[FractalOrbit]) 
             -> MappingResult {
-        let max = self.max_iter as f64;
-        let multiplier = self.strength + 1.0;
-        let vals = vals.iter()
-            .map(|item| {
-                match *item {
-                    FractalOrbit::Bounded => BoundedValue,
-                    FractalOrbit::Escaped(val) => {
-                        let scaled_val = val / max;
-                        let mapped_val = f64::log10(scaled_val * multiplier + 1.0) 
-                            / f64::log10(multiplier + 1.0);
-                        EscapedValue(mapped_val)
-                    }
-                }
-            })
-            .collect::<Vec<_>>();
+        let vals = vals.par_iter()
+            .map(|o| self.map(*o))
+            .collect();
 
         MappingResult{values: vals, width: width, height: height}
     }
+
+    fn maps_samples(&self) -> bool {
+        true
+    }
+}
+
+impl RowMapper for LogarithmicMapper {
+    type Statistics = ();
+
+    fn new_statistics(&self) {}
+
+    fn map_row(&self, _stats: &(), vals: &[FractalOrbit]) -> Vec<MappedCellIntensity> {
+        vals.iter().map(|o| self.map(*o)).collect()
+    }
 }
 
 impl<T: OrbitMapper> AntialiasMapper<T> {
+    // Averages blocks of 2^aa_level by 2^aa_level cells.
     pub fn new(aa_level: u32, mapper: T) -> Self {
-        AntialiasMapper{aa_level: aa_level, mapper: mapper}
+        AntialiasMapper::with_factor(1 << aa_level, ReconstructionFilter::default(), mapper)
+    }
+
+    // Reduces the image by any whole `factor`, weighting cells with `filter`.
+    pub fn with_factor(factor: usize, filter: ReconstructionFilter, mapper: T) -> Self {
+        if factor == 0 {
+            panic!("factor must be at least 1");
+        }
+        AntialiasMapper{factor: factor, filter: filter, mapper: mapper}
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
     }
 }
 
 impl<T: OrbitMapper> OrbitMapper for AntialiasMapper<T> {
     fn map(&self, width: usize, height: usize, vals: &[FractalOrbit]) 
             -> MappingResult {
-        let reduction_factor = 1 << self.aa_level;
-        let flt_reduction_factor = (1 << self.aa_level) as f64;
-        let scaled_width = ((width as f64) / flt_reduction_factor).floor() as usize;
-        let scaled_height = ((height as f64) / flt_reduction_factor).floor() as usize;
+        // A trailing partial block still gets its own pixel.
+        let scaled_width = width.div_ceil(self.factor);
+        let scaled_height = height.div_ceil(self.factor);
 
         let mut out = vec![BoundedValue; scaled_width*scaled_height];
         
@@ -155,33 +311,502 @@ impl<T: OrbitMapper> OrbitMapper for AntialiasMapper<T> {
         let width = full_img.width;
         let orig_values = full_img.values;
 
-        let denom = flt_reduction_factor*flt_reduction_factor;
-        for y in 0..scaled_height {
-            let y_start = y*reduction_factor;
-            for x in 0..scaled_width {
-                let x_start = x*reduction_factor;
-                let mut final_value = 0.0;
-
-                for y0 in y_start..y_start+reduction_factor {
-                    for x0 in x_start..x_start+reduction_factor {
-                        let item = orig_values[x0 + y0*width];
-                        final_value += match item {
-                            BoundedValue => 1.0,
-                            EscapedValue(val) => val,
+        let x_taps = self.filter.taps(width, self.factor, scaled_width);
+        let y_taps = self.filter.taps(full_img.height, self.factor, scaled_height);
+
+        if scaled_width > 0 {
+            out.par_chunks_mut(scaled_width).enumerate().for_each(|(y, out_row)| {
+                let y_taps = &y_taps[y];
+                for (x, out_value) in out_row.iter_mut().enumerate() {
+                    let x_taps = &x_taps[x];
+                    let mut final_value = 0.0;
+                    let mut all_bounded = true;
+
+                    for (j, y_weight) in y_taps.weights.iter().enumerate() {
+                        let row = (y_taps.start + j) * width;
+                        for (i, x_weight) in x_taps.weights.iter().enumerate() {
+                            let weight = x_weight * y_weight;
+                            if weight == 0.0 {
+                                continue;
+                            }
+                            final_value += weight * match orig_values[row + x_taps.start + i] {
+                                BoundedValue => 1.0,
+                                EscapedValue(val) => {
+                                    all_bounded = false;
+                                    val
+                                }
+                            }
                         }
                     }
+
+                    // Filters with negative lobes can overshoot next to sharp
+                    // edges; clamping keeps that from showing up as ringing.
+                    *out_value = 
+                        if all_bounded {
+                            BoundedValue
+                        } else {
+                            EscapedValue(final_value.clamp(0.0, 1.0))
+                        }
                 }
+            });
+        }
 
-                out[x + y*scaled_width] = 
-                    if final_value == 1.0*denom {
-                        BoundedValue
-                    } else {
-                        EscapedValue(
-                            final_value / (flt_reduction_factor*flt_reduction_factor))
-                    }
+        MappingResult{values: out, width: scaled_width, height: scaled_height}
+    }
+}
+
+impl ValueTransform for Gamma {
+    fn apply(&self, val: f64) -> f64 {
+        val.max(0.0).powf(self.0)
+    }
+}
+
+impl ValueTransform for Contrast {
+    fn apply(&self, val: f64) -> f64 {
+        (val - 0.5) * self.contrast + 0.5 + self.brightness
+    }
+}
+
+impl ValueTransform for Invert {
+    fn apply(&self, val: f64) -> f64 {
+        1.0 - val
+    }
+}
+
+impl ValueTransform for Repeat {
+    fn apply(&self, val: f64) -> f64 {
+        let scaled = val * self.0;
+        let wrapped = scaled - scaled.floor();
+        // The top of each repetition stays at 1 rather than wrapping to 0.
+        if wrapped == 0.0 && scaled > 0.0 { 1.0 } else { wrapped }
+    }
+}
+
+impl<F: Fn(f64) -> f64> ValueTransform for F {
+    fn apply(&self, val: f64) -> f64 {
+        self(val)
+    }
+}
+
+impl<T, X: ValueTransform> TransformMapper<T, X> {
+    pub fn new(mapper: T, transform: X) -> Self {
+        TransformMapper{mapper: mapper, transform: transform}
+    }
+
+    pub fn transform(&self) -> &X {
+        &self.transform
+    }
+
+}
+
+// Results are clamped to [0, 1] like every other mapper's.
+fn transform_value<X: ValueTransform>(transform: &X, value: MappedCellIntensity)
+        -> MappedCellIntensity {
+    match value {
+        BoundedValue => BoundedValue,
+        EscapedValue(val) => EscapedValue(transform.apply(val).clamp(0.0, 1.0)),
+    }
+}
+
+impl<T: OrbitMapper, X: ValueTransform + Sync> OrbitMapper for TransformMapper<T, X> {
+    fn map(&self, width: usize, height: usize, vals: &[FractalOrbit])
+            -> MappingResult {
+        let mut result = self.mapper.map(width, height, vals);
+        let transform = &self.transform;
+        result.values.par_iter_mut().for_each(|value| *value = transform_value(transform, *value));
+        result
+    }
+
+    fn maps_samples(&self) -> bool {
+        self.mapper.maps_samples()
+    }
+}
+
+impl<T: RowMapper, X: ValueTransform> RowMapper for TransformMapper<T, X> {
+    type Statistics = T::Statistics;
+
+    fn new_statistics(&self) -> Self::Statistics {
+        self.mapper.new_statistics()
+    }
+
+    fn map_row(&self, stats: &Self::Statistics, vals: &[FractalOrbit])
+            -> Vec<MappedCellIntensity> {
+        self.mapper.map_row(stats, vals).into_iter()
+            .map(|value| transform_value(&self.transform, value))
+            .collect()
+    }
+
+    fn needs_statistics(&self) -> bool {
+        self.mapper.needs_statistics()
+    }
+
+    fn accumulate(&self, stats: &mut Self::Statistics, vals: &[FractalOrbit]) {
+        self.mapper.accumulate(stats, vals)
+    }
+
+    fn finish_statistics(&self, stats: &mut Self::Statistics) {
+        self.mapper.finish_statistics(stats)
+    }
+}
+
+impl<T: OrbitMapper> PercentileClampMapper<T> {
+    pub fn new(low: f64, high: f64, mapper: T) -> Self {
+        if !(0.0 <= low && low < high && high <= 1.0) {
+            panic!("Percentiles must satisfy 0 <= low < high <= 1");
+        }
+        PercentileClampMapper{low: low, high: high, mapper: mapper}
+    }
+}
+
+impl<T: OrbitMapper> OrbitMapper for PercentileClampMapper<T> {
+    fn map(&self, width: usize, height: usize, vals: &[FractalOrbit])
+            -> MappingResult {
+        let mut result = self.mapper.map(width, height, vals);
+
+        let mut escaped: Vec<f64> = result.values.iter()
+            .filter_map(|value| match *value {
+                EscapedValue(val) => Some(val),
+                BoundedValue => None,
+            })
+            .collect();
+        if escaped.is_empty() {
+            return result;
+        }
+        escaped.par_sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        let last = (escaped.len() - 1) as f64;
+        let low = escaped[(self.low * last).round() as usize];
+        let high = escaped[(self.high * last).round() as usize];
+        // A flat range has nothing to stretch.
+        if high <= low {
+            return result;
+        }
+
+        result.values.par_iter_mut().for_each(|value| {
+            if let EscapedValue(val) = *value {
+                *value = EscapedValue(((val - low) / (high - low)).clamp(0.0, 1.0));
             }
+        });
+        result
+    }
+
+    fn maps_samples(&self) -> bool {
+        self.mapper.maps_samples()
+    }
+}
+
+// Maps the orbits of `grid` to one value per cell. A grid sampled more than
+// once per cell has every sample mapped, and each cell's samples are then
+// reduced with `filter`, so the mapper must be able to map samples.
+pub fn map_grid<M: OrbitMapper>(mapper: &M, filter: ReconstructionFilter, grid: &grid::Grid,
+                                vals: &[FractalOrbit]) -> RenderResult<MappingResult> {
+    if grid.num_samples() != vals.len() {
+        return Err(
+            RenderError::OtherError(
+                "Grid and intensities dimensions don't match".to_string()));
+    }
+
+    let (width, height) = (grid.cells_wide(), grid.cells_high());
+    let samples_per_cell = grid.samples_per_cell();
+    if samples_per_cell == 1 {
+        return Ok(mapper.map(width, height, vals));
+    }
+    if !mapper.maps_samples() {
+        return Err(
+            RenderError::OtherError(format!(
+                "Mapper can't map a grid sampled {} times per cell", samples_per_cell)));
+    }
+
+    let samples = mapper.map(width * samples_per_cell, height, vals);
+    Ok(MappingResult{values: reduce_samples(grid, filter, &samples.values),
+        width: width, height: height})
+}
+
+// Reduces the mapped samples of every cell of `grid` to one value, weighting
+// each sample by where it falls in its cell.
+pub fn reduce_samples(grid: &grid::Grid, filter: ReconstructionFilter,
+                      samples: &[MappedCellIntensity]) -> Vec<MappedCellIntensity> {
+    let samples_per_cell = grid.samples_per_cell();
+    if samples_per_cell == 1 {
+        return samples.to_vec();
+    }
+    let width = grid.cells_wide();
+    samples.par_chunks(samples_per_cell).enumerate()
+        .map(|(index, cell)| {
+            let offsets = grid.sample_offsets(index % width, index / width).unwrap();
+            weighted_samples(cell, &filter.sample_weights(&offsets))
+        })
+        .collect()
+}
+
+// Averages the samples of one cell, counting bounded samples as 1.0 like
+// `AntialiasMapper` does. A cell is only bounded if all of its samples are.
+pub fn average_samples(samples: &[MappedCellIntensity]) -> MappedCellIntensity {
+    let weight = 1.0 / (samples.len() as f64);
+    weighted_samples(samples, &vec![weight; samples.len()])
+}
+
+fn weighted_samples(samples: &[MappedCellIntensity], weights: &[f64]) -> MappedCellIntensity {
+    let mut total = 0.0;
+    let mut all_bounded = true;
+    for (sample, weight) in samples.iter().zip(weights.iter()) {
+        total += weight * match *sample {
+            BoundedValue => 1.0,
+            EscapedValue(val) => {
+                all_bounded = false;
+                val
+            }
+        };
+    }
+
+    if all_bounded {
+        BoundedValue
+    } else {
+        EscapedValue(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sampling::SamplePattern;
+    use super::*;
+
+    fn escaped(values: &[f64]) -> Vec<FractalOrbit> {
+        values.iter().map(|val| FractalOrbit::Escaped(*val)).collect()
+    }
+
+    fn intensities(result: &MappingResult) -> Vec<f64> {
+        result.values.iter()
+            .map(|value| match *value {
+                EscapedValue(val) => val,
+                BoundedValue => 1.0,
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
         }
+    }
 
-        MappingResult{values: out, width: scaled_width, height: scaled_height}
+    #[test]
+    fn aa_level_averages_power_of_two_blocks() {
+        let vals = escaped(&[0.0, 2.0, 4.0, 6.0,
+                             2.0, 4.0, 6.0, 8.0]);
+        let result = AntialiasMapper::new(1, LinearMapper::new(8)).map(4, 2, &vals);
+        assert_eq!((result.width, result.height), (2, 1));
+        assert_close(&intensities(&result), &[0.25, 0.75]);
+
+        let level_zero = AntialiasMapper::new(0, LinearMapper::new(8)).map(4, 2, &vals);
+        assert_eq!(level_zero.values, OrbitMapper::map(&LinearMapper::new(8), 4, 2, &vals).values);
+
+        let by_factor = AntialiasMapper::with_factor(2, ReconstructionFilter::Box,
+                                                     LinearMapper::new(8));
+        assert_eq!(by_factor.map(4, 2, &vals).values, result.values);
+    }
+
+    #[test]
+    fn odd_factors_give_trailing_pixels_their_own_block() {
+        // 7x4 cells at a factor of 3: full 3x3 blocks, then partial ones.
+        let vals: Vec<_> = (0..28).map(|i| FractalOrbit::Escaped((i % 7) as f64)).collect();
+        let mapper = AntialiasMapper::with_factor(3, ReconstructionFilter::Box,
+                                                  LinearMapper::new(6));
+        let result = mapper.map(7, 4, &vals);
+        assert_eq!((result.width, result.height), (3, 2));
+        let row = [1.0 / 6.0, 4.0 / 6.0, 1.0];
+        assert_close(&intensities(&result), &[row, row].concat());
+    }
+
+    #[test]
+    fn bounded_blocks_stay_bounded() {
+        let vals = vec![FractalOrbit::Bounded, FractalOrbit::Bounded, FractalOrbit::Escaped(0.0),
+                        FractalOrbit::Bounded, FractalOrbit::Bounded, FractalOrbit::Bounded];
+        let result = AntialiasMapper::with_factor(2, ReconstructionFilter::Box,
+                                                  LinearMapper::new(1)).map(3, 2, &vals);
+        assert_eq!(result.values[0], BoundedValue);
+        assert_eq!(result.values[1], EscapedValue(0.5));
+    }
+
+    #[test]
+    fn filters_preserve_flat_regions_and_stay_in_range() {
+        let filters = [ReconstructionFilter::Box, ReconstructionFilter::Tent,
+                       ReconstructionFilter::Gaussian, ReconstructionFilter::MitchellNetravali,
+                       ReconstructionFilter::Lanczos];
+        let flat = escaped(&[3.0; 10 * 7]);
+        // A hard vertical edge, which negative lobes overshoot.
+        let edge: Vec<_> = (0..10 * 7)
+            .map(|i| FractalOrbit::Escaped(if i % 10 < 5 { 0.0 } else { 4.0 }))
+            .collect();
+
+        for filter in filters.iter() {
+            let mapper = AntialiasMapper::with_factor(3, *filter, LinearMapper::new(4));
+            let result = mapper.map(10, 7, &flat);
+            assert_eq!((result.width, result.height), (4, 3));
+            assert_close(&intensities(&result), &[0.75; 12]);
+
+            let values = intensities(&mapper.map(10, 7, &edge));
+            assert!(values.iter().all(|val| *val >= 0.0 && *val <= 1.0), "{:?}", filter);
+            // Narrow filters don't reach across the edge from the outer pixels.
+            if filter.radius() <= 1.0 {
+                assert_eq!(values[0], 0.0, "{:?}", filter);
+                assert_eq!(values[3], 1.0, "{:?}", filter);
+            }
+        }
+    }
+
+    fn linear(vals: &[FractalOrbit]) -> Vec<MappedCellIntensity> {
+        OrbitMapper::map(&LinearMapper::new(100), vals.len(), 1, vals).values
+    }
+
+    #[test]
+    fn value_transforms() {
+        assert_eq!(Gamma(2.0).apply(0.5), 0.25);
+        assert_eq!(Gamma(0.5).apply(0.25), 0.5);
+        assert_eq!(Gamma(0.5).apply(-0.25), 0.0);
+        assert_eq!(Contrast{contrast: 2.0, brightness: 0.0}.apply(0.75), 1.0);
+        assert_eq!(Contrast{contrast: 2.0, brightness: 0.0}.apply(0.5), 0.5);
+        assert_eq!(Contrast{contrast: 1.0, brightness: 0.25}.apply(0.5), 0.75);
+        assert_eq!(Invert.apply(0.25), 0.75);
+        assert_eq!(Repeat(3.0).apply(0.0), 0.0);
+        assert_eq!(Repeat(4.0).apply(0.125), 0.5);
+        assert_eq!(Repeat(4.0).apply(0.25), 1.0);
+        assert_eq!(Repeat(4.0).apply(0.375), 0.5);
+        assert_eq!(Repeat(4.0).apply(1.0), 1.0);
+        assert_eq!((|val: f64| val * val).apply(0.5), 0.25);
+    }
+
+    #[test]
+    fn transform_mappers_apply_in_order_and_clamp() {
+        let vals = vec![FractalOrbit::Escaped(50.0), FractalOrbit::Bounded,
+                        FractalOrbit::Escaped(10.0)];
+
+        // Gamma first, then the inversion.
+        let nested = TransformMapper::new(
+            TransformMapper::new(LinearMapper::new(100), Gamma(2.0)), Invert);
+        assert_eq!(nested.map(3, 1, &vals).values,
+                   vec![EscapedValue(0.75), BoundedValue, EscapedValue(0.99)]);
+
+        let steep = TransformMapper::new(LinearMapper::new(100),
+                                         Contrast{contrast: 4.0, brightness: 0.0});
+        assert_eq!(steep.map(3, 1, &vals).values,
+                   vec![EscapedValue(0.5), BoundedValue, EscapedValue(0.0)]);
+
+        let closure = TransformMapper::new(LinearMapper::new(100), |val: f64| val + 0.75);
+        assert_eq!(closure.map(3, 1, &vals).values,
+                   vec![EscapedValue(1.0), BoundedValue, EscapedValue(0.85)]);
+        assert_eq!(closure.transform().apply(0.0), 0.75);
+    }
+
+    #[test]
+    fn transform_mappers_pass_statistics_through() {
+        let vals: Vec<_> = (0..40).map(|i| FractalOrbit::Escaped(((i * 7) % 30) as f64)).collect();
+        let mapper = TransformMapper::new(HistogramLinearMapper::new(100), Gamma(0.5));
+        assert!(mapper.needs_statistics());
+
+        let mut stats = mapper.new_statistics();
+        for row in vals.chunks(8) {
+            mapper.accumulate(&mut stats, row);
+        }
+        mapper.finish_statistics(&mut stats);
+        assert_eq!(mapper.map_row(&stats, &vals), mapper.map(40, 1, &vals).values);
+    }
+
+    #[test]
+    fn percentile_clamping_stretches_the_middle() {
+        let mut vals: Vec<_> = (0..101).map(|i| FractalOrbit::Escaped(i as f64)).collect();
+        vals.push(FractalOrbit::Bounded);
+        let result = PercentileClampMapper::new(0.1, 0.9, LinearMapper::new(100))
+            .map(102, 1, &vals);
+        assert_eq!(result.values[0], EscapedValue(0.0));
+        assert_eq!(result.values[10], EscapedValue(0.0));
+        assert_eq!(result.values[50], EscapedValue(0.5));
+        assert_eq!(result.values[90], EscapedValue(1.0));
+        assert_eq!(result.values[100], EscapedValue(1.0));
+        assert_eq!(result.values[101], BoundedValue);
+    }
+
+    #[test]
+    fn percentile_clamping_handles_ties_and_empty_images() {
+        let mapper = PercentileClampMapper::new(0.05, 0.95, LinearMapper::new(100));
+
+        // Most values tied at the low end still stretch the rest.
+        let mut vals = vec![FractalOrbit::Escaped(20.0); 80];
+        vals.extend((0..20).map(|i| FractalOrbit::Escaped(20.0 + 4.0 * (i as f64))));
+        let result = mapper.map(100, 1, &vals);
+        assert_eq!(result.values[0], EscapedValue(0.0));
+        assert_eq!(result.values[99], EscapedValue(1.0));
+
+        // All values tied leaves nothing to stretch.
+        let flat = vec![FractalOrbit::Escaped(30.0); 10];
+        assert_eq!(mapper.map(10, 1, &flat).values, linear(&flat));
+
+        let bounded = vec![FractalOrbit::Bounded; 4];
+        assert_eq!(mapper.map(2, 2, &bounded).values, linear(&bounded));
+        assert!(mapper.map(0, 0, &[]).values.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Percentiles")]
+    fn percentiles_must_be_ordered() {
+        PercentileClampMapper::new(0.9, 0.1, LinearMapper::new(100));
+    }
+
+    #[test]
+    fn multi_sample_grids_are_filtered_per_cell() {
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 3, 2);
+        grid.set_sample_pattern(SamplePattern::Jittered(4));
+        let mut vals: Vec<_> = (0..24).map(|i| FractalOrbit::Escaped(i as f64)).collect();
+        vals[2] = FractalOrbit::Bounded;
+        for val in vals[8..12].iter_mut() {
+            *val = FractalOrbit::Bounded;
+        }
+
+        let filter = ReconstructionFilter::Tent;
+        let mapped = map_grid(&LinearMapper::new(24), filter, &grid, &vals).unwrap();
+        assert_eq!((mapped.width, mapped.height), (3, 2));
+        assert_eq!(mapped.values[2], BoundedValue);
+
+        let samples = intensities(&OrbitMapper::map(&LinearMapper::new(24), 12, 2, &vals));
+        let mut differs_from_average = false;
+        for (index, value) in intensities(&mapped).iter().enumerate() {
+            let offsets = grid.sample_offsets(index % 3, index / 3).unwrap();
+            let cell = &samples[index * 4..index * 4 + 4];
+            let expected: f64 = filter.sample_weights(&offsets).iter().zip(cell.iter())
+                .map(|(weight, sample)| weight * sample)
+                .sum();
+            assert!((value - expected).abs() < 1e-12, "cell {}: {} != {}", index, value, expected);
+            differs_from_average |= (value - cell.iter().sum::<f64>() / 4.0).abs() > 1e-6;
+        }
+        assert!(differs_from_average);
+
+        // Single samples are mapped as they are.
+        let single = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 4, 6);
+        let mapped = map_grid(&LinearMapper::new(24), filter, &single, &vals).unwrap();
+        assert_eq!(mapped.values, OrbitMapper::map(&LinearMapper::new(24), 4, 6, &vals).values);
+    }
+
+    #[test]
+    fn samples_need_a_mapper_that_maps_them_alone() {
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 4, 2);
+        grid.set_sample_pattern(SamplePattern::Regular(2));
+        let vals: Vec<_> = (0..32).map(|i| FractalOrbit::Escaped(i as f64)).collect();
+        let filter = ReconstructionFilter::Gaussian;
+
+        assert!(map_grid(&LinearMapper::new(32), filter, &grid, &vals[..31]).is_err());
+        assert!(map_grid(&AntialiasMapper::new(1, LinearMapper::new(32)), filter, &grid, &vals)
+                .is_err());
+        let clamped = PercentileClampMapper::new(0.1, 0.9, LogarithmicMapper::new(32, 10.0));
+        assert!(map_grid(&TransformMapper::new(clamped, Invert), filter, &grid, &vals).is_ok());
+        assert!(map_grid(&HistogramLinearMapper::new(32), filter, &grid, &vals).is_ok());
+        let nested = TransformMapper::new(AntialiasMapper::new(1, LinearMapper::new(32)), Invert);
+        assert!(map_grid(&nested, filter, &grid, &vals).is_err());
+
+        // Without extra samples any mapper will do.
+        grid.set_sample_pattern(SamplePattern::Center);
+        let mapped = map_grid(&AntialiasMapper::new(1, LinearMapper::new(32)), filter, &grid,
+                              &vals[..8]).unwrap();
+        assert_eq!((mapped.width, mapped.height), (2, 1));
     }
 }
@@ -1,16 +1,37 @@
 use std::fmt;
+use std::io;
 use std::error::Error;
 use image;
 use grid;
 use fractal::{FractalOrbit};
+use runner::{RunnerError};
 
+pub mod adaptive;
+pub mod clahe;
+pub mod color;
+pub mod cycle;
+pub mod exponential;
+pub mod filter;
 pub mod grayscale;
+pub mod histogram;
 pub mod map;
+pub mod shading;
+pub mod stream;
+pub use self::adaptive::AdaptiveAntialiaser;
+pub use self::clahe::ClaheMapper;
+pub use self::color::{ColorFormat, ColorFractalRenderer};
+pub use self::cycle::PaletteCycler;
+pub use self::exponential::{ExponentialMap, ExponentialMapRenderer};
+pub use self::filter::ReconstructionFilter;
 pub use self::grayscale::GrayscaleFractalRenderer;
+pub use self::shading::{Lighting, ShadedFractalRenderer};
+pub use self::stream::{StreamingRenderer, PngRowWriter};
 
 #[derive(Debug)]
 pub enum RenderError {
     ImageError(image::ImageError),
+    RunnerError(RunnerError),
+    IoError(io::Error),
     OtherError(String),
 }
 
@@ -25,6 +46,8 @@ impl fmt::Display for RenderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             RenderError::ImageError(ref e) => e.fmt(f),
+            RenderError::RunnerError(ref e) => e.fmt(f),
+            RenderError::IoError(ref e) => e.fmt(f),
             RenderError::OtherError(ref msg) => {
                 write!(f, "{}", msg)
             }
@@ -36,6 +59,8 @@ impl Error for RenderError {
     fn description(&self) -> &str {
         match *self {
             RenderError::ImageError(ref e) => e.description(),
+            RenderError::RunnerError(ref e) => e.description(),
+            RenderError::IoError(ref e) => e.description(),
             RenderError::OtherError(ref msg) => &msg,
         }
     }
@@ -43,6 +68,8 @@ impl Error for RenderError {
     fn cause(&self) -> Option<&Error> {
         match *self {
             RenderError::ImageError(ref e) => Some(e),
+            RenderError::RunnerError(ref e) => Some(e),
+            RenderError::IoError(ref e) => Some(e),
             RenderError::OtherError(_) => None,
         }
     }
@@ -53,3 +80,15 @@ impl From<image::ImageError> for RenderError {
         RenderError::ImageError(other)
     }
 }
+
+impl From<RunnerError> for RenderError {
+    fn from(other: RunnerError) -> Self {
+        RenderError::RunnerError(other)
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(other: io::Error) -> Self {
+        RenderError::IoError(other)
+    }
+}
@@ -0,0 +1,335 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use grid;
+use image;
+use fractal::{FractalOrbit};
+use palette::{Color, Palette};
+use render::{RenderError, FractalRenderer, RenderResult};
+use super::color::{ColorFormat, colors_to_image};
+use super::filter::{ReconstructionFilter, DEFAULT_SAMPLE_FILTER};
+use super::map::{OrbitMapper, MappedCellIntensity, MappingResult, map_grid};
+
+// Directions ambient occlusion looks for higher ground in.
+const OCCLUSION_DIRECTIONS: [(isize, isize); 8] =
+    [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+// Looks up to `radius` cells away for terrain rising above each cell, and
+// darkens the ambient light by `strength` times how much of the sky it hides.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub radius: usize,
+    pub strength: f64,
+}
+
+// Blinn-Phong lighting of the relief. Angles are in radians; `azimuth` is
+// counterclockwise from the right of the image and `elevation` up from the
+// image plane. Heights are mapped values times `height_scale`, in cells.
+// `strength` blends from the flat base colors at 0 to fully lit at 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lighting {
+    pub azimuth: f64,
+    pub elevation: f64,
+    pub height_scale: f64,
+    pub ambient: f64,
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+    pub occlusion: Option<AmbientOcclusion>,
+    pub strength: f64,
+}
+
+// Renders the mapped escape time field as a lit height map, colored through a
+// palette. Bounded cells form a plateau at height 1, like `AntialiasMapper`
+// counts them, colored with the palette's interior color. Orbits carry only
+// their escape time, so there is no distance estimate to take heights or
+// normals from; heights always come from the mapper. Grids with several
+// samples per cell are reduced to one height per cell with `filter`.
+pub struct ShadedFractalRenderer<T> {
+    mapper: T,
+    palette: Palette,
+    lighting: Lighting,
+    format: ColorFormat,
+    filter: ReconstructionFilter,
+}
+
+impl Default for Lighting {
+    // Light from the upper left, the direction relief is usually read from.
+    fn default() -> Self {
+        Lighting{azimuth: 0.75 * PI, elevation: 0.25 * PI, height_scale: 40.0,
+            ambient: 0.35, diffuse: 0.65, specular: 0.25, shininess: 20.0,
+            occlusion: None, strength: 1.0}
+    }
+}
+
+impl Lighting {
+    // Unit vector toward the light.
+    pub fn direction(&self) -> [f64; 3] {
+        let (sin_el, cos_el) = self.elevation.sin_cos();
+        let (sin_az, cos_az) = self.azimuth.sin_cos();
+        [cos_el * cos_az, cos_el * sin_az, sin_el]
+    }
+}
+
+impl<T: OrbitMapper> ShadedFractalRenderer<T> {
+    pub fn new(mapper: T, palette: Palette) -> Self {
+        ShadedFractalRenderer{mapper: mapper, palette: palette, lighting: Lighting::default(),
+            format: ColorFormat::Rgb, filter: DEFAULT_SAMPLE_FILTER}
+    }
+
+    pub fn set_lighting(&mut self, lighting: Lighting) {
+        self.lighting = lighting;
+    }
+
+    pub fn set_format(&mut self, format: ColorFormat) {
+        self.format = format;
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+}
+
+impl<T: OrbitMapper> FractalRenderer for ShadedFractalRenderer<T> {
+    fn render(&self, grid: &grid::Grid, intensities: &[FractalOrbit])
+            -> RenderResult<image::DynamicImage> {
+        let mapped_result = map_grid(&self.mapper, self.filter, grid, intensities)?;
+
+        render_shaded(&mapped_result, &self.palette, &self.lighting, self.format)
+    }
+}
+
+pub fn render_shaded(mapped_result: &MappingResult, palette: &Palette, lighting: &Lighting,
+                     format: ColorFormat) -> RenderResult<image::DynamicImage> {
+    let (width, height) = (mapped_result.width, mapped_result.height);
+    if mapped_result.values.len() != width * height {
+        return Err(
+            RenderError::OtherError(
+                "Mapped values don't match the image dimensions".to_string()));
+    }
+
+    let heights: Vec<f64> = mapped_result.values.iter()
+        .map(|value| lighting.height_scale * match *value {
+            MappedCellIntensity::BoundedValue => 1.0,
+            MappedCellIntensity::EscapedValue(val) => val,
+        })
+        .collect();
+    let field = HeightField{heights: &heights, width: width, height: height};
+
+    let light = lighting.direction();
+    let halfway = normalize([light[0], light[1], light[2] + 1.0]);
+
+    let colors: Vec<_> = mapped_result.values.par_iter().enumerate()
+        .map(|(index, value)| {
+            let (x, y) = (index % width, index / width);
+            let normal = field.normal(x, y);
+            let diffuse = dot(normal, light).max(0.0);
+            let specular = dot(normal, halfway).max(0.0).powf(lighting.shininess);
+            let ambient = match lighting.occlusion {
+                Some(occlusion) => lighting.ambient
+                    * (1.0 - occlusion.strength * field.occlusion(x, y, occlusion.radius)).max(0.0),
+                None => lighting.ambient,
+            };
+
+            let base = palette.color(*value);
+            let light = ambient + lighting.diffuse * diffuse;
+            let highlight = lighting.specular * specular;
+            let lit = Color::with_alpha(base.r * light + highlight, base.g * light + highlight,
+                                        base.b * light + highlight, base.a);
+            base.lerp(&lit.clipped(), lighting.strength).clipped()
+        })
+        .collect();
+
+    Ok(colors_to_image(width, height, &colors, format))
+}
+
+struct HeightField<'a> {
+    heights: &'a [f64],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> HeightField<'a> {
+    fn at(&self, x: usize, y: usize) -> f64 {
+        self.heights[y * self.width + x]
+    }
+
+    // Surface normal from central differences, one-sided at the edges. Rows
+    // run down the image, so the row slope is negated to keep +y pointing up.
+    fn normal(&self, x: usize, y: usize) -> [f64; 3] {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(self.height - 1));
+
+        let dx = if right > left {
+            (self.at(right, y) - self.at(left, y)) / ((right - left) as f64)
+        } else {
+            0.0
+        };
+        let dy = if down > up {
+            (self.at(x, up) - self.at(x, down)) / ((down - up) as f64)
+        } else {
+            0.0
+        };
+        normalize([-dx, -dy, 1.0])
+    }
+
+    // Fraction of the sky above cell (x, y) hidden by the highest terrain in
+    // each direction, from 0 for open ground to 1 for the bottom of a well.
+    fn occlusion(&self, x: usize, y: usize, radius: usize) -> f64 {
+        let center = self.at(x, y);
+        let mut hidden = 0.0;
+        for &(dx, dy) in OCCLUSION_DIRECTIONS.iter() {
+            let step_length = ((dx * dx + dy * dy) as f64).sqrt();
+            let mut max_slope: f64 = 0.0;
+            for step in 1..(radius + 1) {
+                let sx = (x as isize) + dx * (step as isize);
+                let sy = (y as isize) + dy * (step as isize);
+                if sx < 0 || sy < 0 || sx >= self.width as isize || sy >= self.height as isize {
+                    break;
+                }
+                let rise = self.at(sx as usize, sy as usize) - center;
+                max_slope = max_slope.max(rise / (step_length * (step as f64)));
+            }
+            hidden += max_slope.atan() / (0.5 * PI);
+        }
+        hidden / (OCCLUSION_DIRECTIONS.len() as f64)
+    }
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = dot(v, v).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::GradientStop;
+    use sampling::SamplePattern;
+    use render::map::{AntialiasMapper, LinearMapper};
+    use super::*;
+
+    fn white() -> Palette {
+        Palette::new(vec![GradientStop::new(0.0, Color::new(1.0, 1.0, 1.0))])
+    }
+
+    fn shade(width: usize, height: usize, heights: &[f64], lighting: &Lighting) -> Vec<u8> {
+        let values = heights.iter().map(|h| MappedCellIntensity::EscapedValue(*h)).collect();
+        let mapped = MappingResult{values: values, width: width, height: height};
+        render_shaded(&mapped, &white(), lighting, ColorFormat::Rgb).unwrap()
+            .to_rgb().into_raw()
+    }
+
+    // Heights rising from left to right.
+    fn ramp(width: usize, height: usize) -> Vec<f64> {
+        (0..width * height).map(|i| ((i % width) as f64) / (width as f64)).collect()
+    }
+
+    fn matte() -> Lighting {
+        Lighting{specular: 0.0, ambient: 0.1, diffuse: 0.8, ..Lighting::default()}
+    }
+
+    #[test]
+    fn flat_fields_shade_uniformly() {
+        let lighting = Lighting::default();
+        let pixels = shade(6, 5, &[0.5; 30], &lighting);
+
+        // Facing straight up, only the light's elevation matters.
+        let light = lighting.direction();
+        let halfway = normalize([light[0], light[1], light[2] + 1.0]);
+        let highlight = lighting.specular * halfway[2].powf(lighting.shininess);
+        let level = lighting.ambient + lighting.diffuse * lighting.elevation.sin() + highlight;
+        let expected = Color::new(level, level, level).to_rgba8()[0];
+        assert!(pixels.iter().all(|pixel| *pixel == expected), "{:?} != {}", pixels, expected);
+
+        let mut palette = white();
+        palette.set_interior(Color::new(0.0, 0.0, 1.0));
+        let mapped = MappingResult{values: vec![MappedCellIntensity::BoundedValue; 12],
+                                   width: 4, height: 3};
+        let pixels = render_shaded(&mapped, &palette, &lighting, ColorFormat::Rgb).unwrap()
+            .to_rgb().into_raw();
+        let expected = Color::new(highlight, highlight, level).to_rgba8();
+        for pixel in pixels.chunks(3) {
+            assert_eq!(pixel, &expected[..3]);
+        }
+    }
+
+    #[test]
+    fn flipping_the_light_flips_the_shading() {
+        let (width, height) = (8, 4);
+        let from_left = Lighting{azimuth: PI, ..matte()};
+        let from_right = Lighting{azimuth: 0.0, ..matte()};
+
+        // The ramp faces left, so it's lit brighter from the left.
+        let lit = shade(width, height, &ramp(width, height), &from_left);
+        let unlit = shade(width, height, &ramp(width, height), &from_right);
+        assert!(lit.iter().zip(unlit.iter()).all(|(a, b)| a > b));
+
+        // Mirroring the ramp and the light gives the same image, mirrored.
+        let mirrored: Vec<_> = ramp(width, height).chunks(width)
+            .flat_map(|row| row.iter().rev().cloned().collect::<Vec<_>>())
+            .collect();
+        let flipped = shade(width, height, &mirrored, &from_right);
+        for (row, flipped_row) in lit.chunks(width * 3).zip(flipped.chunks(width * 3)) {
+            let reversed: Vec<_> = flipped_row.chunks(3).rev()
+                .flat_map(|pixel| pixel.iter().cloned())
+                .collect();
+            assert_eq!(row, &reversed[..]);
+        }
+
+        // With no strength the relief disappears.
+        let unshaded = shade(width, height, &ramp(width, height),
+                             &Lighting{strength: 0.0, ..from_left});
+        assert!(unshaded.iter().all(|pixel| *pixel == 255));
+    }
+
+    #[test]
+    fn occlusion_darkens_pits() {
+        let mut heights = vec![0.5; 25];
+        heights[12] = 0.0;
+        let occluded = Lighting{occlusion: Some(AmbientOcclusion{radius: 2, strength: 1.0}),
+                                ambient: 0.5, ..matte()};
+        let open = shade(5, 5, &heights, &matte());
+        let pixels = shade(5, 5, &heights, &occluded);
+        assert!(pixels[12 * 3] < pixels[0], "{:?}", pixels);
+        // The pit's floor faces straight up, so only occlusion darkens it.
+        assert!(open[12 * 3] > pixels[12 * 3]);
+    }
+
+    #[test]
+    fn multi_sample_grids_render_a_pixel_per_cell() {
+        let cells: Vec<_> = (0..24)
+            .map(|i| if i % 5 == 0 {
+                FractalOrbit::Bounded
+            } else {
+                FractalOrbit::Escaped((i % 11) as f64 + 0.3)
+            })
+            .collect();
+        let samples: Vec<_> = cells.iter().flat_map(|cell| vec![*cell; 3]).collect();
+        let mut grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 6, 4);
+        let renderer = ShadedFractalRenderer::new(LinearMapper::new(11), white());
+        let expected = renderer.render(&grid, &cells).unwrap().to_rgb().into_raw();
+
+        grid.set_sample_pattern(SamplePattern::Jittered(3));
+        let image = renderer.render(&grid, &samples).unwrap().to_rgb().into_raw();
+        assert_eq!(image.len(), expected.len());
+        assert!(image.iter().zip(expected.iter()).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1),
+                "{:?} != {:?}", image, expected);
+
+        let aa = ShadedFractalRenderer::new(AntialiasMapper::new(1, LinearMapper::new(11)),
+                                            white());
+        assert!(aa.render(&grid, &samples).is_err());
+    }
+}
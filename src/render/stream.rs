@@ -0,0 +1,399 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{Write, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2;
+use flate2::write::ZlibEncoder;
+
+use encoding;
+use grid;
+use fractal::{FractalOrbit};
+use runner::{FractalRunner};
+use render::{RenderError, RenderResult};
+use super::filter::{ReconstructionFilter, DEFAULT_SAMPLE_FILTER};
+use super::grayscale::intensity_to_luma;
+use super::map::{RowMapper, reduce_samples};
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const IDAT_CHUNK_SIZE: usize = 1 << 16;
+
+pub trait ImageRowWriter {
+    fn write_row(&mut self, row: &[u8]) -> RenderResult<()>;
+    fn finish(&mut self) -> RenderResult<()>;
+}
+
+// Writes an 8-bit grayscale PNG one row at a time. Compressed data is split
+// into fixed-size IDAT chunks as it is produced, so memory use does not depend
+// on the image size.
+pub struct PngRowWriter<W: Write> {
+    encoder: Option<ZlibEncoder<IdatWriter<W>>>,
+    width: usize,
+    rows_remaining: usize,
+}
+
+struct IdatWriter<W> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+// Renders a grid in horizontal batches, mapping and writing each batch before
+// computing the next. Mappers that need whole-image statistics get a first
+// pass over the grid, whose orbits are spilled to disk and read back for the
+// second unless the renderer is told to recompute them. Grids with several
+// samples per cell are reduced to one value per cell with `filter`.
+pub struct StreamingRenderer<M> {
+    mapper: M,
+    rows_per_batch: usize,
+    spill_path: Option<PathBuf>,
+    recompute: bool,
+    filter: ReconstructionFilter,
+}
+
+// Numbers the temporary spill files of one process, so renders running at the
+// same time don't share one.
+static NEXT_SPILL: AtomicUsize = AtomicUsize::new(0);
+
+impl<M: RowMapper> StreamingRenderer<M> {
+    pub fn new(mapper: M, rows_per_batch: usize) -> Self {
+        if rows_per_batch == 0 {
+            panic!("rows_per_batch must be at least 1");
+        }
+        StreamingRenderer{mapper: mapper, rows_per_batch: rows_per_batch, spill_path: None,
+            recompute: false, filter: DEFAULT_SAMPLE_FILTER}
+    }
+
+    pub fn set_filter(&mut self, filter: ReconstructionFilter) {
+        self.filter = filter;
+    }
+
+    // Where the first pass's orbits are spilled; a file in the system's
+    // temporary directory by default. The file is removed once the render is
+    // done.
+    pub fn set_spill_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.spill_path = Some(path.as_ref().to_path_buf());
+    }
+
+    // Computes the grid a second time rather than spilling the first pass's
+    // orbits, trading twice the fractal computation for no disk use.
+    pub fn set_recompute(&mut self, recompute: bool) {
+        self.recompute = recompute;
+    }
+
+    // Runs `runner` once over every strip, or twice when the mapper needs
+    // statistics and the renderer recomputes rather than spills; see
+    // `set_recompute`.
+    pub fn render<R, W>(&self, runner: &R, grid: &grid::Grid, writer: &mut W)
+            -> RenderResult<()> where R: FractalRunner, W: ImageRowWriter {
+        if self.mapper.needs_statistics() && !self.recompute {
+            let path = self.spill_path.clone().unwrap_or_else(temp_spill_path);
+            let result = self.render_spilled(runner, grid, &path, writer);
+            let removed = fs::remove_file(&path);
+            result?;
+            removed?;
+            return writer.finish();
+        }
+
+        let mut stats = self.mapper.new_statistics();
+        if self.mapper.needs_statistics() {
+            for strip in grid.iter_strips(self.rows_per_batch) {
+                self.mapper.accumulate(&mut stats, &compute_strip(runner, grid, &strip)?);
+            }
+            self.mapper.finish_statistics(&mut stats);
+        }
+
+        for strip in grid.iter_strips(self.rows_per_batch) {
+            let orbits = compute_strip(runner, grid, &strip)?;
+            self.write_rows(&grid.strip_grid(&strip), &stats, &orbits, writer)?;
+        }
+
+        writer.finish()
+    }
+
+    // Gathers statistics while spilling every strip's orbits to `path`, then
+    // maps the strips read back from it.
+    fn render_spilled<R, W>(&self, runner: &R, grid: &grid::Grid, path: &Path, writer: &mut W)
+            -> RenderResult<()> where R: FractalRunner, W: ImageRowWriter {
+        let mut stats = self.mapper.new_statistics();
+        let mut spill = BufWriter::new(fs::File::create(path)?);
+        for strip in grid.iter_strips(self.rows_per_batch) {
+            let orbits = compute_strip(runner, grid, &strip)?;
+            self.mapper.accumulate(&mut stats, &orbits);
+            encoding::write_orbits(&mut spill, &orbits)?;
+        }
+        spill.into_inner().map_err(|e| e.into_error())?;
+        self.mapper.finish_statistics(&mut stats);
+
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        for strip in grid.iter_strips(self.rows_per_batch) {
+            let orbits = encoding::read_orbits(&mut reader, grid.num_samples_in_strip(&strip))?;
+            self.write_rows(&grid.strip_grid(&strip), &stats, &orbits, writer)?;
+        }
+        Ok(())
+    }
+
+    // Writes the rows of `strip_grid`, the part of the grid `orbits` cover.
+    fn write_rows<W: ImageRowWriter>(&self, strip_grid: &grid::Grid, stats: &M::Statistics,
+                                     orbits: &[FractalOrbit], writer: &mut W)
+            -> RenderResult<()> {
+        let cells = reduce_samples(strip_grid, self.filter, &self.mapper.map_row(stats, orbits));
+        for row in cells.chunks(strip_grid.cells_wide()) {
+            let pixels: Vec<_> = row.iter().map(|cell| intensity_to_luma(*cell)).collect();
+            writer.write_row(&pixels)?;
+        }
+        Ok(())
+    }
+}
+
+fn temp_spill_path() -> PathBuf {
+    let index = NEXT_SPILL.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("fractal-spill-{}-{}", process::id(), index))
+}
+
+fn compute_strip<R: FractalRunner>(runner: &R, grid: &grid::Grid, strip: &grid::GridStrip)
+        -> RenderResult<Vec<FractalOrbit>> {
+    let orbits = runner.run(&grid.strip_grid(strip))?;
+    if orbits.len() != grid.num_samples_in_strip(strip) {
+        return Err(RenderError::OtherError(format!(
+            "{} produced {} samples, expected {}",
+            strip, orbits.len(), grid.num_samples_in_strip(strip))));
+    }
+    Ok(orbits)
+}
+
+impl<W: Write> PngRowWriter<W> {
+    pub fn new(mut inner: W, width: usize, height: usize) -> RenderResult<Self> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&u32_bytes(width as u32));
+        header.extend_from_slice(&u32_bytes(height as u32));
+        // 8 bits per sample, grayscale, deflate, adaptive filtering, no interlace.
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        inner.write_all(&PNG_SIGNATURE)?;
+        write_chunk(&mut inner, b"IHDR", &header)?;
+
+        let idat = IdatWriter{inner: inner, buffer: Vec::with_capacity(IDAT_CHUNK_SIZE)};
+        Ok(PngRowWriter{
+            encoder: Some(ZlibEncoder::new(idat, flate2::Compression::Default)),
+            width: width, rows_remaining: height})
+    }
+}
+
+impl<W: Write> ImageRowWriter for PngRowWriter<W> {
+    fn write_row(&mut self, row: &[u8]) -> RenderResult<()> {
+        if row.len() != self.width {
+            return Err(RenderError::OtherError(format!(
+                "Row has {} pixels, expected {}", row.len(), self.width)));
+        }
+        if self.rows_remaining == 0 {
+            return Err(RenderError::OtherError("Too many rows written".to_string()));
+        }
+        let encoder = match self.encoder {
+            Some(ref mut encoder) => encoder,
+            None => return Err(RenderError::OtherError("Image already finished".to_string())),
+        };
+
+        // Each scanline is prefixed with its filter type; 0 is unfiltered.
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+        self.rows_remaining -= 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> RenderResult<()> {
+        if self.rows_remaining != 0 {
+            return Err(RenderError::OtherError(format!(
+                "Image finished with {} rows missing", self.rows_remaining)));
+        }
+        if let Some(encoder) = self.encoder.take() {
+            let mut idat = encoder.finish()?;
+            idat.flush()?;
+            write_chunk(&mut idat.inner, b"IEND", &[])?;
+            idat.inner.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= IDAT_CHUNK_SIZE {
+            write_chunk(&mut self.inner, b"IDAT", &self.buffer[..IDAT_CHUNK_SIZE])?;
+            self.buffer.drain(..IDAT_CHUNK_SIZE);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.inner, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        self.inner.flush()
+    }
+}
+
+fn u32_bytes(val: u32) -> [u8; 4] {
+    [(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&u32_bytes(data.len() as u32))?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&u32_bytes(crc32(kind.iter().chain(data.iter()))))
+}
+
+fn crc32<'a, I: Iterator<Item=&'a u8>>(bytes: I) -> u32 {
+    let crc = bytes.fold(0xffffffffu32, |crc, byte| {
+        let mut crc = crc ^ (*byte as u32);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+        crc
+    });
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use image;
+
+    use fractal::FractalOrbit;
+    use mandelbrot::Mandelbrot;
+    use runner::{RunnerError, SyncronousRunner};
+    use sampling::{self, SamplePattern};
+    use render::{FractalRenderer, GrayscaleFractalRenderer};
+    use render::map::{HistogramLinearMapper, LogarithmicMapper, OrbitMapper};
+    use super::*;
+
+    fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let image = image::load_from_memory(png).unwrap().to_luma();
+        let (width, height) = image.dimensions();
+        (width, height, image.into_raw())
+    }
+
+    #[test]
+    fn png_rows_decode() {
+        // Noise doesn't compress, so the image spans several IDAT chunks.
+        let (width, height) = (300, 250);
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| (sampling::unit_random(i as u64) * 256.0) as u8)
+            .collect();
+
+        let mut png = Vec::new();
+        {
+            let mut writer = PngRowWriter::new(&mut png, width, height).unwrap();
+            for row in pixels.chunks(width) {
+                writer.write_row(row).unwrap();
+            }
+            assert!(writer.write_row(&pixels[..width - 1]).is_err());
+            writer.finish().unwrap();
+            assert!(writer.write_row(&pixels[..width]).is_err());
+        }
+        assert!(png.len() > IDAT_CHUNK_SIZE);
+        assert_eq!(decode(&png), (width as u32, height as u32, pixels));
+    }
+
+    #[test]
+    fn unfinished_images_are_errors() {
+        let mut png = Vec::new();
+        let mut writer = PngRowWriter::new(&mut png, 3, 2).unwrap();
+        writer.write_row(&[1, 2, 3]).unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    fn assert_streams_like_in_memory<M, N>(grid: &grid::Grid, streamed: StreamingRenderer<M>,
+                                           in_memory: N)
+            where M: RowMapper, N: OrbitMapper {
+        let runner = SyncronousRunner::new(Mandelbrot::new(100));
+
+        let mut png = Vec::new();
+        {
+            let mut writer = PngRowWriter::new(&mut png, grid.cells_wide(),
+                                               grid.cells_high()).unwrap();
+            streamed.render(&runner, grid, &mut writer).unwrap();
+        }
+
+        let expected = GrayscaleFractalRenderer::new(in_memory)
+            .render(grid, &runner.run(grid).unwrap()).unwrap()
+            .to_luma().into_raw();
+        assert_eq!(decode(&png),
+                   (grid.cells_wide() as u32, grid.cells_high() as u32, expected));
+    }
+
+    #[test]
+    fn streamed_images_match_in_memory_renders() {
+        let mut grid = grid::Grid::centered((-0.75, 0.1), 2.0, 90, 70);
+        assert_streams_like_in_memory(&grid, StreamingRenderer::new(
+            LogarithmicMapper::new(100, 100.0), 16), LogarithmicMapper::new(100, 100.0));
+
+        // Histogram statistics are gathered over the whole image first.
+        assert_streams_like_in_memory(&grid, StreamingRenderer::new(
+            HistogramLinearMapper::new(100), 16), HistogramLinearMapper::new(100));
+
+        let spill_path = env::temp_dir().join(format!("stream-spill-{}", process::id()));
+        let mut spilled = StreamingRenderer::new(HistogramLinearMapper::new(100), 16);
+        spilled.set_spill_path(&spill_path);
+        assert_streams_like_in_memory(&grid, spilled, HistogramLinearMapper::new(100));
+        assert!(!spill_path.exists());
+
+        grid.set_sample_pattern(SamplePattern::Regular(2));
+        assert_streams_like_in_memory(&grid, StreamingRenderer::new(
+            HistogramLinearMapper::new(100), 7), HistogramLinearMapper::new(100));
+        grid.set_sample_pattern(SamplePattern::Jittered(5));
+        assert_streams_like_in_memory(&grid, StreamingRenderer::new(
+            LogarithmicMapper::new(100, 100.0), 16), LogarithmicMapper::new(100, 100.0));
+    }
+
+    // Counts its runs.
+    struct CountingRunner {
+        runner: SyncronousRunner<Mandelbrot>,
+        runs: AtomicUsize,
+    }
+
+    impl FractalRunner for CountingRunner {
+        fn run(&self, grid: &grid::Grid) -> Result<Vec<FractalOrbit>, RunnerError> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            self.runner.run(grid)
+        }
+    }
+
+    #[test]
+    fn spilling_computes_each_strip_once() {
+        let grid = grid::Grid::centered((-0.75, 0.1), 2.0, 90, 70);
+        let runner = CountingRunner{runner: SyncronousRunner::new(Mandelbrot::new(100)),
+                                    runs: AtomicUsize::new(0)};
+        let render = |renderer: &StreamingRenderer<HistogramLinearMapper>| {
+            let mut png = Vec::new();
+            {
+                let mut writer = PngRowWriter::new(&mut png, 90, 70).unwrap();
+                renderer.render(&runner, &grid, &mut writer).unwrap();
+            }
+            (decode(&png), runner.runs.swap(0, Ordering::SeqCst))
+        };
+
+        // 70 rows make 5 strips of up to 16.
+        let mut renderer = StreamingRenderer::new(HistogramLinearMapper::new(100), 16);
+        let (spilled, runs) = render(&renderer);
+        assert_eq!(runs, 5);
+
+        renderer.set_recompute(true);
+        let (recomputed, runs) = render(&renderer);
+        assert_eq!(runs, 10);
+        assert_eq!(recomputed, spilled);
+
+        let spill_path = env::temp_dir().join(format!("stream-count-{}", process::id()));
+        renderer.set_recompute(false);
+        renderer.set_spill_path(&spill_path);
+        assert_eq!(render(&renderer), (spilled, 5));
+        assert!(!spill_path.exists());
+    }
+}
@@ -0,0 +1,320 @@
+use std::cmp;
+use std::sync;
+
+use fractal::{FractalOrbit, FractalParameters};
+use grid;
+use runner::{FractalRunner, RunnerError, RunnerResult};
+
+// Tolerance, in cells, when deciding whether two grids share a pixel lattice.
+const LATTICE_TOLERANCE: f64 = 1e-6;
+
+// Remembers the result of the last run. A following run over a grid on the
+// same pixel lattice, such as after panning by whole pixels, copies the
+// overlapping cells and only computes the newly exposed ones. Results are
+// keyed by the runner's fractal parameters.
+pub struct CachedRunner<R> {
+    runner: R,
+    cached: sync::Mutex<Option<CachedResult>>,
+}
+
+struct CachedResult {
+    fractal_params: String,
+    grid: grid::Grid,
+    values: Vec<FractalOrbit>,
+}
+
+impl<R: FractalRunner + FractalParameters> CachedRunner<R> {
+    pub fn new(runner: R) -> Self {
+        CachedRunner{runner: runner, cached: sync::Mutex::new(None)}
+    }
+
+    pub fn runner(&self) -> &R {
+        &self.runner
+    }
+
+    // Results cached under other fractal parameters are never reused.
+    pub fn set_runner(&mut self, runner: R) {
+        self.runner = runner;
+    }
+
+    pub fn clear(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+
+    fn compute_tile(&self, grid: &grid::Grid, tile: &grid::GridTile,
+                    values: &mut [FractalOrbit]) -> RunnerResult<()> {
+        if tile.num_cells() == 0 {
+            return Ok(());
+        }
+
+        let data = self.runner.run(&grid.tile_grid(tile))?;
+        if data.len() != grid.num_samples_in_tile(tile) {
+            return Err(RunnerError::RunnerError(format!(
+                "Runner produced {} samples for {}, expected {}",
+                data.len(), tile, grid.num_samples_in_tile(tile))));
+        }
+
+        grid.place_tile(tile, &data, values);
+        Ok(())
+    }
+}
+
+// Returns the offset, in whole cells, of `grid`'s first cell within `cached`,
+// or None if the two grids don't lie on the same lattice.
+fn lattice_offset(cached: &grid::Grid, grid: &grid::Grid) -> Option<(isize, isize)> {
+    if grid.sample_pattern() != cached.sample_pattern() {
+        return None;
+    }
+
+    // Both cell edges of `grid`, measured in cells of `cached`, must be one
+    // cell long and parallel to the lattice axes.
+    let transform = grid.transform();
+    let cached_transform = cached.transform();
+    let step_x = cached_transform.invert(cached_transform.origin.0 + transform.column_step.0,
+                                         cached_transform.origin.1 + transform.column_step.1);
+    let step_y = cached_transform.invert(cached_transform.origin.0 + transform.row_step.0,
+                                         cached_transform.origin.1 + transform.row_step.1);
+    match (step_x, step_y) {
+        (Some((xx, xy)), Some((yx, yy))) => {
+            let tolerance = LATTICE_TOLERANCE / (cmp::max(grid.cells_wide(),
+                                                          grid.cells_high()) as f64);
+            if (xx - 1.0).abs() > tolerance || xy.abs() > tolerance
+                    || yx.abs() > tolerance || (yy - 1.0).abs() > tolerance {
+                return None;
+            }
+        },
+        _ => return None,
+    }
+
+    let (x, y) = grid.pixel_to_complex(0.0, 0.0);
+    let (lattice_x, lattice_y) = cached_transform.invert(x, y)?;
+    let (cached_column, cached_row) = cached.cell_offset();
+    let offset_x = lattice_x - (cached_column as f64);
+    let offset_y = lattice_y - (cached_row as f64);
+    if (offset_x - offset_x.round()).abs() > LATTICE_TOLERANCE
+            || (offset_y - offset_y.round()).abs() > LATTICE_TOLERANCE {
+        return None;
+    }
+
+    Some((offset_x.round() as isize, offset_y.round() as isize))
+}
+
+// The range of cells in a row (or column) of length `len` that are covered by
+// a cached row of length `cached_len` starting `offset` cells earlier.
+fn overlap(offset: isize, len: usize, cached_len: usize) -> (usize, usize) {
+    let start = cmp::max(0, -offset);
+    let end = cmp::min(len as isize, cached_len as isize - offset);
+    if end <= start {
+        (0, 0)
+    } else {
+        (start as usize, end as usize)
+    }
+}
+
+impl<R: FractalRunner + FractalParameters> FractalParameters for CachedRunner<R> {
+    fn parameters(&self) -> String {
+        self.runner.parameters()
+    }
+}
+
+impl<R: FractalRunner + FractalParameters> FractalRunner for CachedRunner<R> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let fractal_params = self.runner.parameters();
+        let mut cached = self.cached.lock().unwrap();
+
+        let offset = match *cached {
+            Some(ref cached) if cached.fractal_params == fractal_params =>
+                lattice_offset(&cached.grid, grid),
+            _ => None,
+        };
+
+        let values = match offset {
+            Some((offset_x, offset_y)) => {
+                let cached = cached.as_ref().unwrap();
+                let (x_start, x_end) =
+                    overlap(offset_x, grid.cells_wide(), cached.grid.cells_wide());
+                let (y_start, y_end) =
+                    overlap(offset_y, grid.cells_high(), cached.grid.cells_high());
+
+                let samples_per_cell = grid.samples_per_cell();
+                let mut values = vec![FractalOrbit::Bounded; grid.num_samples()];
+                if x_end > x_start {
+                    for y in y_start..y_end {
+                        let cached_y = (y as isize + offset_y) as usize;
+                        let cached_x = (x_start as isize + offset_x) as usize;
+                        let src = cached.grid.row_sample_start(cached_y)
+                            + cached_x * samples_per_cell;
+                        let dst = grid.row_sample_start(y) + x_start * samples_per_cell;
+                        let len = (x_end - x_start) * samples_per_cell;
+                        values[dst..dst+len].copy_from_slice(&cached.values[src..src+len]);
+                    }
+                }
+
+                let (y_start, y_end) = if x_end > x_start {
+                    (y_start, y_end)
+                } else {
+                    (0, 0)
+                };
+                let exposed = [
+                    grid::GridTile::new(0, 0, grid.cells_wide(), y_start),
+                    grid::GridTile::new(0, y_end, grid.cells_wide(), grid.cells_high() - y_end),
+                    grid::GridTile::new(0, y_start, x_start, y_end - y_start),
+                    grid::GridTile::new(x_end, y_start, grid.cells_wide() - x_end,
+                                        y_end - y_start),
+                ];
+                for tile in exposed.iter() {
+                    self.compute_tile(grid, tile, &mut values)?;
+                }
+                values
+            },
+            None => self.runner.run(grid)?,
+        };
+
+        *cached = Some(CachedResult{fractal_params: fractal_params,
+            grid: grid.clone(), values: values.clone()});
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use num_complex::Complex;
+    use fractal::Fractal;
+    use runner::SyncronousRunner;
+    use sampling::SamplePattern;
+    use super::*;
+
+    #[derive(Clone)]
+    struct PlaneFractal {
+        scale: f64,
+    }
+
+    impl Fractal for PlaneFractal {
+        fn test(&self, c: Complex<f64>) -> FractalOrbit {
+            FractalOrbit::Escaped(self.scale * (c.re * 1000.0 + c.im))
+        }
+    }
+
+    impl FractalParameters for PlaneFractal {
+        fn parameters(&self) -> String {
+            format!("scale={}", self.scale)
+        }
+    }
+
+    // Counts the samples it computes.
+    struct CountingRunner {
+        runner: SyncronousRunner<PlaneFractal>,
+        samples: AtomicUsize,
+    }
+
+    impl CountingRunner {
+        fn new(fractal: PlaneFractal) -> Self {
+            CountingRunner{runner: SyncronousRunner::new(fractal), samples: AtomicUsize::new(0)}
+        }
+
+        fn take_count(&self) -> usize {
+            self.samples.swap(0, Ordering::SeqCst)
+        }
+    }
+
+    impl FractalRunner for CountingRunner {
+        fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+            self.samples.fetch_add(grid.num_samples(), Ordering::SeqCst);
+            self.runner.run(grid)
+        }
+    }
+
+    impl FractalParameters for CountingRunner {
+        fn parameters(&self) -> String {
+            self.runner.parameters()
+        }
+    }
+
+    // A 60x40 grid of 0.05 wide cells, panned by whole cells.
+    fn panned(columns: f64, rows: f64, pattern: SamplePattern) -> grid::Grid {
+        let (left, top) = (-2.0 + 0.05 * columns, 1.0 - 0.05 * rows);
+        let mut grid = grid::Grid::new(left, top, left + 3.0, top - 2.0, 60, 40);
+        grid.set_sample_pattern(pattern);
+        grid
+    }
+
+    fn assert_close(a: &[FractalOrbit], b: &[FractalOrbit]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            match (*a, *b) {
+                (FractalOrbit::Escaped(a), FractalOrbit::Escaped(b)) =>
+                    assert!((a - b).abs() < 1e-9, "{} != {}", a, b),
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn panned_results_match_a_fresh_run() {
+        let fractal = PlaneFractal{scale: 1.0};
+        let patterns = [SamplePattern::Center, SamplePattern::Jittered(4),
+                        SamplePattern::Stratified(2)];
+        for &pattern in patterns.iter() {
+            let runner = CachedRunner::new(CountingRunner::new(fractal.clone()));
+            let fresh = SyncronousRunner::new(fractal.clone());
+            let spc = pattern.samples_per_cell();
+
+            let first = panned(0.0, 0.0, pattern);
+            assert_close(&runner.run(&first).unwrap(), &fresh.run(&first).unwrap());
+            assert_eq!(runner.runner().take_count(), 60 * 40 * spc);
+
+            // Each pan exposes the cells the previous grid didn't cover.
+            let pans = [(5.0, 0.0, 5 * 40), (5.0, 3.0, 3 * 60),
+                        (-2.0, -1.0, 7 * 40 + 4 * 60 - 7 * 4), (0.0, 0.0, 2 * 40 + 60 - 2),
+                        (0.0, 0.0, 0)];
+            for &(columns, rows, exposed) in pans.iter() {
+                let grid = panned(columns, rows, pattern);
+                assert_close(&runner.run(&grid).unwrap(), &fresh.run(&grid).unwrap());
+                assert_eq!(runner.runner().take_count(), exposed * spc);
+            }
+        }
+    }
+
+    #[test]
+    fn unrelated_grids_are_computed_in_full() {
+        let fractal = PlaneFractal{scale: 1.0};
+        let runner = CachedRunner::new(CountingRunner::new(fractal.clone()));
+        runner.run(&panned(0.0, 0.0, SamplePattern::Center)).unwrap();
+        runner.runner().take_count();
+
+        // Half a cell off the lattice, a different pattern and a zoom.
+        runner.run(&panned(0.5, 0.0, SamplePattern::Center)).unwrap();
+        assert_eq!(runner.runner().take_count(), 60 * 40);
+        runner.run(&panned(0.5, 0.0, SamplePattern::Jittered(2))).unwrap();
+        assert_eq!(runner.runner().take_count(), 60 * 40 * 2);
+        runner.run(&grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 120, 80)).unwrap();
+        assert_eq!(runner.runner().take_count(), 120 * 80);
+    }
+
+    #[test]
+    fn changed_parameters_invalidate_the_cache() {
+        let fractal = PlaneFractal{scale: 1.0};
+        let mut runner = CachedRunner::new(CountingRunner::new(fractal.clone()));
+        runner.run(&panned(0.0, 0.0, SamplePattern::Center)).unwrap();
+        assert_eq!(runner.parameters(), "scale=1");
+
+        let changed = PlaneFractal{scale: 2.0};
+        runner.set_runner(CountingRunner::new(changed.clone()));
+        assert_eq!(runner.parameters(), "scale=2");
+        let grid = panned(5.0, 0.0, SamplePattern::Center);
+        let values = runner.run(&grid).unwrap();
+        assert_eq!(runner.runner().take_count(), 60 * 40);
+        assert_close(&values, &SyncronousRunner::new(changed).run(&grid).unwrap());
+
+        // Swapping back to the same parameters reuses the cache again.
+        runner.set_runner(CountingRunner::new(PlaneFractal{scale: 2.0}));
+        runner.run(&panned(6.0, 0.0, SamplePattern::Center)).unwrap();
+        assert_eq!(runner.runner().take_count(), 40);
+
+        runner.clear();
+        runner.run(&grid).unwrap();
+        assert_eq!(runner.runner().take_count(), 60 * 40);
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use encoding;
+use fractal::{FractalOrbit, FractalParameters};
+use grid;
+use runner::{FractalRunner, RunnerError, RunnerResult};
+
+const CHECKPOINT_MAGIC: &[u8] = b"FRACKPT";
+const CHECKPOINT_VERSION: u64 = 2;
+
+// A checkpoint file is a header describing the render, followed by one record
+// per completed strip. Records are appended and synced one at a time, so a
+// crash can at worst leave a partial record at the end, which is discarded
+// when the checkpoint is resumed.
+pub struct CheckpointRunner<R> {
+    runner: R,
+    path: PathBuf,
+    rows_per_checkpoint: usize,
+}
+
+struct CompletedStrip {
+    strip: grid::GridStrip,
+    values: Vec<FractalOrbit>,
+}
+
+// The render parameters come from the runner itself, so a checkpoint can only
+// be resumed by a runner computing the same fractal.
+impl<R: FractalRunner + FractalParameters> CheckpointRunner<R> {
+    pub fn new<P: AsRef<Path>>(runner: R, path: P, rows_per_checkpoint: usize) -> Self {
+        if rows_per_checkpoint == 0 {
+            panic!("rows_per_checkpoint must be at least 1");
+        }
+        CheckpointRunner{runner: runner, path: path.as_ref().to_path_buf(),
+            rows_per_checkpoint: rows_per_checkpoint}
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn header(&self, grid: &grid::Grid) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(CHECKPOINT_MAGIC);
+        encoding::write_u64(&mut header, CHECKPOINT_VERSION).unwrap();
+        encoding::write_grid(&mut header, grid).unwrap();
+        encoding::write_string(&mut header, &self.runner.parameters()).unwrap();
+        header
+    }
+
+    fn open_checkpoint(&self, grid: &grid::Grid, values: &mut [FractalOrbit],
+                       completed_rows: &mut [bool]) -> RunnerResult<fs::File> {
+        let header = self.header(grid);
+
+        let mut file = fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(false).open(&self.path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        // A crash while the header was being written leaves it cut short, and
+        // nothing has been computed yet, so the checkpoint is started over.
+        let header_len = match read_header_len(&contents) {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&header)?;
+                file.sync_data()?;
+                return Ok(file);
+            },
+            Err(err) => return Err(RunnerError::RunnerError(format!(
+                "Checkpoint {} is invalid: {}", self.path.display(), err))),
+        };
+
+        if contents[..header_len] != header[..] {
+            return Err(RunnerError::RunnerError(format!(
+                "Checkpoint {} was written for different render parameters",
+                self.path.display())));
+        }
+
+        let mut reader = io::Cursor::new(&contents[header_len..]);
+        let mut valid_len = header_len as u64;
+        while let Ok(completed) = read_record(&mut reader) {
+            let strip = &completed.strip;
+            if strip.start + strip.height > grid.cells_high()
+                    || completed.values.len() != grid.num_samples_in_strip(strip) {
+                break;
+            }
+
+            let strip_start = grid.row_sample_start(strip.start);
+            let strip_end = strip_start + completed.values.len();
+            values[strip_start..strip_end].copy_from_slice(&completed.values);
+            for done in &mut completed_rows[strip.start..strip.start+strip.height] {
+                *done = true;
+            }
+            valid_len = header_len as u64 + reader.position();
+        }
+
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::Start(valid_len))?;
+        Ok(file)
+    }
+}
+
+// The length of the header at the start of `contents`, failing with
+// `UnexpectedEof` if it was cut short.
+fn read_header_len(contents: &[u8]) -> io::Result<usize> {
+    let magic_len = contents.len().min(CHECKPOINT_MAGIC.len());
+    if contents[..magic_len] != CHECKPOINT_MAGIC[..magic_len] {
+        return Err(encoding::invalid_data("not a checkpoint file"));
+    }
+
+    let mut reader = io::Cursor::new(contents);
+    reader.set_position(magic_len as u64);
+    if magic_len < CHECKPOINT_MAGIC.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated checkpoint header"));
+    }
+    if encoding::read_u64(&mut reader)? != CHECKPOINT_VERSION {
+        return Err(encoding::invalid_data("unsupported checkpoint version"));
+    }
+    encoding::read_grid(&mut reader)?;
+    encoding::read_string(&mut reader)?;
+    Ok(reader.position() as usize)
+}
+
+fn read_record<R: Read>(reader: &mut R) -> io::Result<CompletedStrip> {
+    let payload_len = encoding::read_u64(reader)?;
+    let mut payload = Vec::new();
+    reader.take(payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != payload_len {
+        return Err(encoding::invalid_data("truncated checkpoint record"));
+    }
+    if encoding::read_u64(reader)? != encoding::checksum(&payload) {
+        return Err(encoding::invalid_data("checkpoint record checksum mismatch"));
+    }
+
+    let mut payload = io::Cursor::new(payload);
+    let strip = encoding::read_strip(&mut payload)?;
+    // Every orbit takes at least a byte.
+    let max_len = payload.get_ref().len();
+    let values = encoding::read_orbits(&mut payload, max_len)?;
+    Ok(CompletedStrip{strip: strip, values: values})
+}
+
+fn write_record(file: &mut fs::File, strip: &grid::GridStrip, values: &[FractalOrbit])
+        -> io::Result<()> {
+    let mut payload = Vec::new();
+    encoding::write_strip(&mut payload, strip)?;
+    encoding::write_orbits(&mut payload, values)?;
+
+    let mut record = Vec::with_capacity(payload.len() + 16);
+    encoding::write_u64(&mut record, payload.len() as u64)?;
+    record.extend_from_slice(&payload);
+    encoding::write_u64(&mut record, encoding::checksum(&payload))?;
+
+    file.write_all(&record)?;
+    file.sync_data()
+}
+
+impl<R: FractalRunner + FractalParameters> FractalRunner for CheckpointRunner<R> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let mut values = vec![FractalOrbit::Bounded; grid.num_samples()];
+        let mut completed_rows = vec![false; grid.cells_high()];
+
+        let mut file = self.open_checkpoint(grid, &mut values, &mut completed_rows)?;
+
+        for strip in grid.iter_strips(self.rows_per_checkpoint) {
+            let rows = strip.start..strip.start+strip.height;
+            if completed_rows[rows].iter().all(|done| *done) {
+                continue;
+            }
+
+            let data = self.runner.run(&grid.strip_grid(&strip))?;
+            if data.len() != grid.num_samples_in_strip(&strip) {
+                return Err(RunnerError::RunnerError(format!(
+                    "{} produced {} samples, expected {}",
+                    strip, data.len(), grid.num_samples_in_strip(&strip))));
+            }
+
+            let strip_start = grid.row_sample_start(strip.start);
+            values[strip_start..strip_start+data.len()].copy_from_slice(&data);
+            write_record(&mut file, &strip, &data)?;
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use mandelbrot::Mandelbrot;
+    use runner::SyncronousRunner;
+    use super::*;
+
+    // Counts the rows it computes, and fails once it has run `fail_after`
+    // strips, the way a killed render would stop.
+    struct InterruptedRunner {
+        runner: SyncronousRunner<Mandelbrot>,
+        fail_after: usize,
+        strips: AtomicUsize,
+        rows: AtomicUsize,
+    }
+
+    impl InterruptedRunner {
+        fn new(iter_limit: usize, fail_after: usize) -> Self {
+            InterruptedRunner{runner: SyncronousRunner::new(Mandelbrot::new(iter_limit)),
+                fail_after: fail_after, strips: AtomicUsize::new(0), rows: AtomicUsize::new(0)}
+        }
+    }
+
+    impl FractalParameters for InterruptedRunner {
+        fn parameters(&self) -> String {
+            self.runner.parameters()
+        }
+    }
+
+    impl FractalRunner for InterruptedRunner {
+        fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+            if self.strips.fetch_add(1, Ordering::SeqCst) >= self.fail_after {
+                return Err(RunnerError::RunnerError("interrupted".to_string()));
+            }
+            self.rows.fetch_add(grid.cells_high(), Ordering::SeqCst);
+            self.runner.run(grid)
+        }
+    }
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("checkpoint-{}-{}.ckpt", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn grid() -> grid::Grid {
+        grid::Grid::centered((-0.5, 0.0), 3.0, 24, 20)
+    }
+
+    #[test]
+    fn resumes_after_an_interrupted_run() {
+        let path = checkpoint_path("resume");
+        let expected = SyncronousRunner::new(Mandelbrot::new(50)).run(&grid()).unwrap();
+
+        let interrupted = CheckpointRunner::new(InterruptedRunner::new(50, 3), &path, 4);
+        assert!(interrupted.run(&grid()).is_err());
+
+        // Only the strips after the last record are computed again.
+        let resumed = CheckpointRunner::new(InterruptedRunner::new(50, usize::MAX), &path, 4);
+        assert_eq!(resumed.run(&grid()).unwrap(), expected);
+        assert_eq!(resumed.runner.rows.load(Ordering::SeqCst), 20 - 3 * 4);
+
+        // A partly written record at the end is discarded.
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+        let truncated = CheckpointRunner::new(InterruptedRunner::new(50, usize::MAX), &path, 4);
+        assert_eq!(truncated.run(&grid()).unwrap(), expected);
+        assert_eq!(truncated.runner.rows.load(Ordering::SeqCst), 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_checkpoints_for_other_renders() {
+        let path = checkpoint_path("mismatch");
+        let runner = CheckpointRunner::new(InterruptedRunner::new(50, 2), &path, 4);
+        assert!(runner.run(&grid()).is_err());
+
+        let other_params = CheckpointRunner::new(InterruptedRunner::new(60, usize::MAX), &path, 4);
+        let err = other_params.run(&grid()).unwrap_err();
+        assert!(err.to_string().contains("different render parameters"), "{}", err);
+
+        let mut other_grid = grid();
+        other_grid.set_sample_pattern(::sampling::SamplePattern::Regular(2));
+        let same_params = CheckpointRunner::new(InterruptedRunner::new(50, usize::MAX), &path, 4);
+        let err = same_params.run(&other_grid).unwrap_err();
+        assert!(err.to_string().contains("different render parameters"), "{}", err);
+        assert_eq!(same_params.runner.rows.load(Ordering::SeqCst), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recreates_partly_written_headers() {
+        let path = checkpoint_path("header");
+        let runner = CheckpointRunner::new(InterruptedRunner::new(50, usize::MAX), &path, 4);
+        let header = runner.header(&grid());
+
+        for len in [3, CHECKPOINT_MAGIC.len(), header.len() - 1].iter() {
+            fs::write(&path, &header[..*len]).unwrap();
+            let runner = CheckpointRunner::new(InterruptedRunner::new(50, usize::MAX), &path, 4);
+            assert!(runner.run(&grid()).is_ok());
+            assert_eq!(runner.runner.rows.load(Ordering::SeqCst), 20);
+        }
+
+        // Files that aren't checkpoints are left alone.
+        fs::write(&path, b"not a checkpoint").unwrap();
+        assert!(runner.run(&grid()).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"not a checkpoint");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
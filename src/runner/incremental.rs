@@ -0,0 +1,147 @@
+use std::sync;
+
+use rayon::prelude::*;
+
+use num_complex::{Complex};
+use fractal::{FractalOrbit, FractalParameters, IncrementalFractal, OrbitState};
+use grid;
+use runner::{FractalRunner, RunnerResult};
+
+// Keeps the orbit state of every cell from the previous run. When the same
+// grid is run again with a higher iteration limit, only cells that were still
+// bounded are iterated further, starting from where they stopped.
+pub struct IncrementalRunner<T> {
+    fractal: T,
+    previous: sync::Mutex<Option<PreviousRun>>,
+}
+
+struct PreviousRun {
+    grid: grid::Grid,
+    iter_limit: usize,
+    values: Vec<FractalOrbit>,
+    states: Vec<OrbitState>,
+}
+
+impl<T: IncrementalFractal + Send + Sync> IncrementalRunner<T> {
+    pub fn new(fractal: T) -> Self {
+        IncrementalRunner{fractal: fractal, previous: sync::Mutex::new(None)}
+    }
+
+    pub fn fractal(&self) -> &T {
+        &self.fractal
+    }
+
+    pub fn iteration_limit(&self) -> usize {
+        self.fractal.iteration_limit()
+    }
+
+    pub fn set_iteration_limit(&mut self, iter_limit: usize) {
+        self.fractal = self.fractal.with_iteration_limit(iter_limit);
+    }
+
+    pub fn clear(&self) {
+        *self.previous.lock().unwrap() = None;
+    }
+}
+
+impl<T: IncrementalFractal + FractalParameters> FractalParameters for IncrementalRunner<T> {
+    fn parameters(&self) -> String {
+        self.fractal.parameters()
+    }
+}
+
+impl<T: IncrementalFractal + Send + Sync> FractalRunner for IncrementalRunner<T> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let iter_limit = self.fractal.iteration_limit();
+        let mut previous = self.previous.lock().unwrap();
+
+        let reusable = match *previous {
+            Some(ref run) => run.grid == *grid && run.iter_limit <= iter_limit,
+            None => false,
+        };
+        if !reusable {
+            *previous = Some(PreviousRun{grid: grid.clone(), iter_limit: 0,
+                values: vec![FractalOrbit::Bounded; grid.num_samples()],
+                states: vec![OrbitState::new(); grid.num_samples()]});
+        }
+
+        let run = previous.as_mut().unwrap();
+        if run.iter_limit < iter_limit && grid.num_cells() > 0 {
+            let fractal = &self.fractal;
+            let samples_per_cell = grid.samples_per_cell();
+            let row_len = grid.cells_wide() * samples_per_cell;
+
+            run.values.par_chunks_mut(row_len)
+                .zip(run.states.par_chunks_mut(row_len))
+                .enumerate()
+                .for_each(|(y, (values, states))| {
+                    let cells = values.chunks_mut(samples_per_cell)
+                        .zip(states.chunks_mut(samples_per_cell))
+                        .enumerate();
+                    for (x, (values, states)) in cells {
+                        if values.iter().all(|value| *value != FractalOrbit::Bounded) {
+                            continue;
+                        }
+                        let positions = grid.sample_positions(x, y).unwrap();
+                        let samples = values.iter_mut().zip(states.iter_mut()).zip(positions);
+                        for ((value, state), (re, im)) in samples {
+                            if *value == FractalOrbit::Bounded {
+                                *value = fractal.continue_orbit(Complex::new(re, im), state);
+                            }
+                        }
+                    }
+                });
+            run.iter_limit = iter_limit;
+        }
+
+        Ok(run.values.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mandelbrot::Mandelbrot;
+    use runner::SyncronousRunner;
+    use sampling::SamplePattern;
+    use super::*;
+
+    fn fresh_run(iter_limit: usize, grid: &grid::Grid) -> Vec<FractalOrbit> {
+        SyncronousRunner::new(Mandelbrot::new(iter_limit)).run(grid).unwrap()
+    }
+
+    #[test]
+    fn raised_limits_match_a_fresh_run() {
+        let mut grid = grid::Grid::centered((-0.75, 0.1), 0.5, 80, 60);
+        for pattern in [SamplePattern::Center, SamplePattern::Regular(2)].iter() {
+            grid.set_sample_pattern(*pattern);
+            let mut runner = IncrementalRunner::new(Mandelbrot::new(50));
+            assert!(runner.run(&grid).unwrap() == fresh_run(50, &grid));
+
+            runner.set_iteration_limit(500);
+            let values = runner.run(&grid).unwrap();
+            assert!(values == fresh_run(500, &grid));
+            assert!(values != fresh_run(50, &grid));
+
+            // Steps in between end up in the same place.
+            let mut stepped = IncrementalRunner::new(Mandelbrot::new(50));
+            for &iter_limit in [50, 120, 121, 500].iter() {
+                stepped.set_iteration_limit(iter_limit);
+                stepped.run(&grid).unwrap();
+            }
+            assert!(stepped.run(&grid).unwrap() == values);
+        }
+    }
+
+    #[test]
+    fn lowered_limits_and_other_grids_start_over() {
+        let grid = grid::Grid::centered((-0.75, 0.1), 0.5, 40, 30);
+        let mut runner = IncrementalRunner::new(Mandelbrot::new(500));
+        runner.run(&grid).unwrap();
+
+        runner.set_iteration_limit(50);
+        assert!(runner.run(&grid).unwrap() == fresh_run(50, &grid));
+
+        let other = grid::Grid::centered((-0.7, 0.1), 0.5, 40, 30);
+        assert!(runner.run(&other).unwrap() == fresh_run(50, &other));
+    }
+}
@@ -0,0 +1,428 @@
+
+use std::thread;
+use std::io;
+use std::panic;
+use std::sync;
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::fmt;
+use std::error::Error;
+use std::any::Any;
+
+use num_complex::{Complex};
+use fractal::{Fractal, FractalOrbit, FractalParameters};
+use grid;
+
+pub mod cache;
+pub mod checkpoint;
+pub mod incremental;
+pub mod network;
+pub mod rayon_runner;
+pub use self::cache::CachedRunner;
+pub use self::checkpoint::CheckpointRunner;
+pub use self::incremental::IncrementalRunner;
+pub use self::network::NetworkRunner;
+pub use self::rayon_runner::RayonRunner;
+
+#[derive(Debug)]
+pub enum RunnerError {
+    RunnerError(String),
+    SendError(Box<Error + Send + 'static>),
+    RecvError(mpsc::RecvError),
+    ThreadError(Box<Any + Send + 'static>),
+    IoError(io::Error),
+    WorkerPanic {
+        tile: grid::GridTile,
+        cell: (usize, usize),
+        position: Complex<f64>,
+        message: String,
+    },
+}
+type RunnerResult<T> = Result<T, RunnerError>;
+
+pub trait FractalRunner {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>>;
+}
+
+pub struct SyncronousRunner<T: Fractal> {
+    fractal: T,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PanicPolicy {
+    Fail,
+    Retry(usize),
+    Fill(FractalOrbit),
+}
+
+pub struct MultiThreadedRunner<T> {
+    fractal: T,
+    num_threads: usize,
+    panic_policy: PanicPolicy,
+    tile_size: (usize, usize),
+}
+
+// Square tiles keep each worker's cells close together in the plane, which
+// also spreads expensive regions more evenly than full-width strips.
+pub const DEFAULT_TILE_SIZE: usize = 64;
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunnerError::SendError(ref err) => err.fmt(f),
+            RunnerError::RecvError(ref err) => err.fmt(f),
+            RunnerError::RunnerError(ref msg) => 
+                write!(f, "{}", msg),
+            RunnerError::ThreadError(_) => write!(f, "thread error"),
+            RunnerError::IoError(ref err) => err.fmt(f),
+            RunnerError::WorkerPanic{ref tile, cell, position, ref message} =>
+                write!(f, "fractal panicked in {} at cell ({}, {}), c = {}: {}",
+                       tile, cell.0, cell.1, position, message),
+        }
+    }
+}
+
+impl Error for RunnerError {
+    fn description(&self) -> &str {
+        match *self {
+            RunnerError::SendError(ref err) => err.description(),
+            RunnerError::RecvError(ref err) => err.description(),
+            RunnerError::RunnerError(ref msg) =>
+                &msg,
+            RunnerError::ThreadError(_) => "thread error",
+            RunnerError::IoError(ref err) => err.description(),
+            RunnerError::WorkerPanic{..} => "fractal panicked in worker thread",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            RunnerError::SendError(ref err) => Some(&**err),
+            RunnerError::RecvError(ref err) => Some(err),
+            RunnerError::IoError(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<mpsc::RecvError> for RunnerError {
+    fn from(other: mpsc::RecvError) -> Self {
+        RunnerError::RecvError(other)
+    }
+}
+
+impl From<io::Error> for RunnerError {
+    fn from(other: io::Error) -> Self {
+        RunnerError::IoError(other)
+    }
+}
+
+impl<T: Send + 'static> From<mpsc::SendError<T>> for RunnerError {
+    fn from(other: mpsc::SendError<T>) -> Self {
+        RunnerError::SendError(Box::new(other))
+    }
+}
+
+impl From<Box<Any + Send + 'static>> for RunnerError {
+    fn from(other: Box<Any + Send + 'static>) -> Self {
+        RunnerError::ThreadError(other)
+    }
+}
+
+impl<T: Fractal> SyncronousRunner<T> {
+    pub fn new(fractal: T) -> SyncronousRunner<T> {
+        SyncronousRunner::<T>{fractal: fractal}
+    }
+}
+
+impl<'a, T: Fractal> SyncronousRunner<T> {
+    pub fn fractal(&'a self) -> &'a T {
+        &self.fractal
+    }
+}
+
+impl<T: Fractal> FractalRunner for SyncronousRunner<T> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let values = grid.iter_samples()
+            .map(|(_, (x,y))| self.fractal.test(Complex::new(x, y)))
+            .collect();
+
+        Ok(values)
+    }
+}
+
+impl<T: Fractal + FractalParameters> FractalParameters for SyncronousRunner<T> {
+    fn parameters(&self) -> String {
+        self.fractal.parameters()
+    }
+}
+
+impl<T: Fractal + Send + Sync + 'static> MultiThreadedRunner<T> {
+    pub fn new(fractal: T, num_threads: usize) -> Self {
+        MultiThreadedRunner{fractal: fractal, num_threads: num_threads,
+            panic_policy: PanicPolicy::Fail,
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE)}
+    }
+
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    pub fn set_tile_size(&mut self, width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            panic!("Tile size must be at least 1x1");
+        }
+        self.tile_size = (width, height);
+    }
+
+    fn execute_workers(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let mut values = vec![FractalOrbit::Bounded; grid.num_samples()];
+
+        {
+            let (tile_width, tile_height) = self.tile_size;
+            let work = grid.split_tiles(tile_width, tile_height, &mut values);
+            let work = sync::Mutex::new(work.into_iter());
+
+            let abort = AtomicBool::new(false);
+
+            let fractal = &self.fractal;
+            let policy = self.panic_policy;
+            let work = &work;
+            let abort = &abort;
+            let result: RunnerResult<()> = thread::scope(|scope| {
+                let threads: Vec<_> = (0..self.num_threads)
+                    .map(|_| scope.spawn(move || {
+                        thread_worker(fractal, grid, work, policy, abort)
+                    }))
+                    .collect();
+
+                let mut result = Ok(());
+                for thread in threads {
+                    let ret = match thread.join() {
+                        Ok(ret) => ret,
+                        Err(e) => Err(RunnerError::from(e)),
+                    };
+                    if result.is_ok() {
+                        result = ret;
+                    }
+                }
+                result
+            });
+            result?;
+        }
+
+        Ok(values)
+    }
+}
+
+fn thread_worker<'a, T, I>(fractal: &T, grid: &grid::Grid, work: &sync::Mutex<I>,
+                           policy: PanicPolicy, abort: &AtomicBool) -> RunnerResult<()>
+        where T: Fractal, I: Iterator<Item=(grid::GridTile, Vec<&'a mut [FractalOrbit]>)> {
+    while !abort.load(Ordering::Relaxed) {
+        let item = work.lock().unwrap().next();
+        let (tile, mut output) = match item {
+            Some(item) => item,
+            None => break,
+        };
+
+        let mut attempts = 0;
+        while let Err(err) = compute_tile(fractal, grid, &tile, &mut output, policy) {
+            match policy {
+                PanicPolicy::Retry(max_attempts) if attempts < max_attempts => attempts += 1,
+                _ => {
+                    abort.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Computes `tile` into `output`, its rows in the grid's values.
+fn compute_tile<T: Fractal>(fractal: &T, grid: &grid::Grid, tile: &grid::GridTile,
+                            output: &mut [&mut [FractalOrbit]], policy: PanicPolicy)
+        -> RunnerResult<()> {
+    let cells = output.iter_mut().flat_map(|row| row.iter_mut()).zip(tile.iter_samples(grid));
+    for (cell, (index, (x, y))) in cells {
+        let c = Complex::new(x, y);
+        match panic::catch_unwind(panic::AssertUnwindSafe(|| fractal.test(c))) {
+            Ok(orbit) => *cell = orbit,
+            Err(payload) => match policy {
+                PanicPolicy::Fill(sentinel) => *cell = sentinel,
+                _ => {
+                    return Err(RunnerError::WorkerPanic{tile: *tile, cell: index,
+                        position: c, message: panic_message(&payload)});
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+fn panic_message(payload: &Box<Any + Send + 'static>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+impl<T: Fractal + FractalParameters> FractalParameters for MultiThreadedRunner<T> {
+    fn parameters(&self) -> String {
+        self.fractal.parameters()
+    }
+}
+
+impl<T: Fractal + Send + Sync + 'static> FractalRunner for MultiThreadedRunner<T> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        self.execute_workers(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use sampling::SamplePattern;
+
+    use super::*;
+
+    // Encodes the point it was given, so a result in the wrong place shows up
+    // as a mismatch against the cell's own position.
+    #[derive(Clone)]
+    struct PositionFractal;
+
+    impl Fractal for PositionFractal {
+        fn test(&self, c: Complex<f64>) -> FractalOrbit {
+            FractalOrbit::Escaped(c.re * 1e6 + c.im)
+        }
+    }
+
+    fn expected(grid: &grid::Grid) -> Vec<FractalOrbit> {
+        grid.iter_samples()
+            .map(|(_, (x, y))| PositionFractal.test(Complex::new(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn runners_place_cells_on_odd_grids() {
+        let grids = [grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 7, 3),
+                     grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 3, 7),
+                     grid::Grid::new(-0.743, 0.131, -0.742, 0.130, 333, 17),
+                     grid::Grid::centered((-0.25, 0.0), 2.5, 101, 99)];
+
+        for grid in grids.iter() {
+            let expected = expected(grid);
+            for num_threads in 1..5 {
+                let runner = MultiThreadedRunner::new(PositionFractal, num_threads);
+                assert!(runner.run(grid).unwrap() == expected);
+            }
+            for &(width, height) in &[(1, 1), (5, 2), (64, 64), (grid.cells_wide(), 1)] {
+                let mut runner = MultiThreadedRunner::new(PositionFractal, 3);
+                runner.set_tile_size(width, height);
+                assert!(runner.run(grid).unwrap() == expected);
+
+                let mut runner = RayonRunner::new(PositionFractal);
+                runner.set_tile_size(width, height);
+                assert!(runner.run(grid).unwrap() == expected);
+            }
+            assert!(SyncronousRunner::new(PositionFractal).run(grid).unwrap() == expected);
+        }
+    }
+
+    #[test]
+    fn workers_fill_every_sample_in_place() {
+        let mut grid = grid::Grid::centered((-0.5, 0.0), 3.0, 13, 9);
+        grid.set_sample_pattern(SamplePattern::Regular(3));
+        let expected = expected(&grid);
+
+        // More workers than tiles leaves some of them without work.
+        let mut runner = MultiThreadedRunner::new(PositionFractal, 8);
+        runner.set_tile_size(7, 5);
+        assert_eq!(grid.iter_tiles(7, 5).count(), 4);
+        assert!(runner.run(&grid).unwrap() == expected);
+
+        let empty = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 0, 4);
+        assert!(runner.run(&empty).unwrap().is_empty());
+    }
+
+    // Behaves like `PositionFractal`, except that it panics on the cell at
+    // `panic_at` until it has panicked `panics` times.
+    #[derive(Clone)]
+    struct PanickyFractal {
+        panic_at: Complex<f64>,
+        panics: sync::Arc<AtomicUsize>,
+    }
+
+    impl PanickyFractal {
+        fn new(grid: &grid::Grid, cell: (usize, usize), panics: usize) -> Self {
+            let (_, (x, y)) = grid.iter_samples().find(|&(index, _)| index == cell).unwrap();
+            PanickyFractal{panic_at: Complex::new(x, y),
+                panics: sync::Arc::new(AtomicUsize::new(panics))}
+        }
+    }
+
+    impl Fractal for PanickyFractal {
+        fn test(&self, c: Complex<f64>) -> FractalOrbit {
+            if c == self.panic_at && self.panics.load(Ordering::SeqCst) > 0 {
+                self.panics.fetch_sub(1, Ordering::SeqCst);
+                panic!("bad cell");
+            }
+            PositionFractal.test(c)
+        }
+    }
+
+    fn panicky_runner(grid: &grid::Grid, panics: usize, policy: PanicPolicy)
+            -> MultiThreadedRunner<PanickyFractal> {
+        let mut runner = MultiThreadedRunner::new(PanickyFractal::new(grid, (5, 2), panics), 3);
+        runner.set_tile_size(2, 2);
+        runner.set_panic_policy(policy);
+        runner
+    }
+
+    #[test]
+    fn panics_fail_the_run_by_default() {
+        let grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 7, 3);
+        let runner = panicky_runner(&grid, usize::MAX, PanicPolicy::Fail);
+        assert_eq!(runner.panic_policy, PanicPolicy::Fail);
+
+        match runner.run(&grid) {
+            Err(RunnerError::WorkerPanic{tile, cell, position, ref message}) => {
+                assert_eq!(tile, grid::GridTile::new(4, 2, 2, 1));
+                assert_eq!(cell, (5, 2));
+                assert_eq!(position, runner.fractal.panic_at);
+                assert_eq!(message, "bad cell");
+            },
+            other => panic!("expected a worker panic, got {:?}", other),
+        }
+        let err = runner.run(&grid).unwrap_err().to_string();
+        assert!(err.contains("Tile<Columns 4 to 5, rows 2 to 2> at cell (5, 2)"), "{}", err);
+    }
+
+    #[test]
+    fn panicking_tiles_are_retried() {
+        let grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 7, 3);
+        let runner = panicky_runner(&grid, 2, PanicPolicy::Retry(2));
+        assert!(runner.run(&grid).unwrap() == expected(&grid));
+
+        // Tiles that keep panicking still fail once the retries run out.
+        let runner = panicky_runner(&grid, 3, PanicPolicy::Retry(2));
+        match runner.run(&grid) {
+            Err(RunnerError::WorkerPanic{cell, ..}) => assert_eq!(cell, (5, 2)),
+            other => panic!("expected a worker panic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn panicking_cells_are_filled() {
+        let grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 7, 3);
+        let runner = panicky_runner(&grid, usize::MAX, PanicPolicy::Fill(FractalOrbit::Bounded));
+
+        let mut expected = expected(&grid);
+        expected[2 * 7 + 5] = FractalOrbit::Bounded;
+        assert!(runner.run(&grid).unwrap() == expected);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Write, BufReader, BufWriter};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use encoding;
+use fractal::{Fractal, FractalOrbit, FractalParameters, FromParameters};
+use grid;
+use runner::{FractalRunner, RayonRunner, RunnerError, RunnerResult, DEFAULT_TILE_SIZE};
+
+// Wire protocol, all integers little endian as written by `encoding`:
+//
+//   client -> worker: magic, version                      (once per connection)
+//   client -> worker: MSG_JOB, parameters, grid, tile     (per job)
+//   worker -> client: MSG_RESULT, orbits | MSG_ERROR, message
+//
+// A connection is closed by the client once it has no more work.
+const PROTOCOL_MAGIC: &[u8] = b"FRACNET";
+const PROTOCOL_VERSION: u64 = 3;
+
+// How long to wait on a worker before handing its job to another. It has to
+// cover computing a whole tile.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+const MSG_JOB: u8 = 1;
+const MSG_RESULT: u8 = 2;
+const MSG_ERROR: u8 = 3;
+
+pub struct NetworkRunner {
+    fractal_params: String,
+    workers: Vec<SocketAddr>,
+    tile_size: (usize, usize),
+    timeout: Option<Duration>,
+}
+
+struct JobQueue {
+    pending: VecDeque<grid::GridTile>,
+    outstanding: usize,
+    aborted: bool,
+}
+
+struct WorkerConnection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+enum JobResponse {
+    Completed(Vec<FractalOrbit>),
+    Failed(String),
+}
+
+type JobResult = RunnerResult<(grid::GridTile, Vec<FractalOrbit>)>;
+
+impl NetworkRunner {
+    pub fn new<F: FractalParameters>(fractal: &F, workers: Vec<SocketAddr>) -> Self {
+        NetworkRunner{fractal_params: fractal.parameters(), workers: workers,
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE),
+            timeout: Some(Duration::from_secs(DEFAULT_TIMEOUT_SECS))}
+    }
+
+    pub fn set_tile_size(&mut self, width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            panic!("Tile size must be at least 1x1");
+        }
+        self.tile_size = (width, height);
+    }
+
+    // Without a timeout a worker that hangs holds its job forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn workers(&self) -> &[SocketAddr] {
+        &self.workers
+    }
+
+    fn run_worker(&self, addr: &SocketAddr, grid: &grid::Grid,
+                  queue: &(sync::Mutex<JobQueue>, sync::Condvar),
+                  results: mpsc::Sender<JobResult>) {
+        let mut connection = match WorkerConnection::open(addr, self.timeout) {
+            Ok(connection) => connection,
+            Err(_) => return,
+        };
+
+        while let Some(tile) = next_job(queue) {
+            match connection.run_job(&self.fractal_params, grid, &tile) {
+                Ok(JobResponse::Completed(values)) => {
+                    finish_job(queue);
+                    let _ = results.send(Ok((tile, values)));
+                },
+                Ok(JobResponse::Failed(msg)) => {
+                    let _ = results.send(Err(RunnerError::RunnerError(
+                        format!("Worker {} failed on {}: {}", addr, tile, msg))));
+                    return;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                    let _ = results.send(Err(RunnerError::RunnerError(
+                        format!("Worker {} sent an invalid response for {}: {}", addr, tile, e))));
+                    return;
+                },
+                Err(_) => {
+                    // The worker went away; hand its job to someone else.
+                    return_job(queue, tile);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl FractalParameters for NetworkRunner {
+    fn parameters(&self) -> String {
+        self.fractal_params.clone()
+    }
+}
+
+impl FractalRunner for NetworkRunner {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let mut values = vec![FractalOrbit::Bounded; grid.num_samples()];
+
+        let (tile_width, tile_height) = self.tile_size;
+        let pending: VecDeque<_> = grid.iter_tiles(tile_width, tile_height).collect();
+        let queue = (sync::Mutex::new(JobQueue{outstanding: pending.len(),
+                                               pending: pending, aborted: false}),
+                     sync::Condvar::new());
+        let mut error = None;
+
+        {
+            let queue = &queue;
+            let (result_sender, result_receiver) = mpsc::channel();
+
+            thread::scope(|scope| {
+                for addr in self.workers.iter() {
+                    let results = result_sender.clone();
+                    scope.spawn(move || self.run_worker(addr, grid, queue, results));
+                }
+                drop(result_sender);
+
+                for result in result_receiver {
+                    let placed = result.and_then(|(tile, data)| {
+                        if data.len() != grid.num_samples_in_tile(&tile) {
+                            return Err(RunnerError::RunnerError(format!(
+                                "Worker returned {} samples for {}, expected {}",
+                                data.len(), tile, grid.num_samples_in_tile(&tile))));
+                        }
+                        grid.place_tile(&tile, &data, &mut values);
+                        Ok(())
+                    });
+
+                    if let Err(e) = placed {
+                        if error.is_none() {
+                            error = Some(e);
+                        }
+                        abort_jobs(queue);
+                    }
+                }
+            });
+        }
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let remaining = queue.0.lock().unwrap().outstanding;
+        if remaining != 0 {
+            return Err(RunnerError::RunnerError(format!(
+                "All workers disconnected with {} tiles remaining", remaining)));
+        }
+
+        Ok(values)
+    }
+}
+
+fn next_job(queue: &(sync::Mutex<JobQueue>, sync::Condvar)) -> Option<grid::GridTile> {
+    let (ref lock, ref cvar) = *queue;
+    let mut jobs = lock.lock().unwrap();
+    loop {
+        if jobs.aborted {
+            return None;
+        }
+        if let Some(tile) = jobs.pending.pop_front() {
+            return Some(tile);
+        }
+        if jobs.outstanding == 0 {
+            return None;
+        }
+        // Other workers still hold jobs that may be handed back to us.
+        jobs = cvar.wait(jobs).unwrap();
+    }
+}
+
+fn finish_job(queue: &(sync::Mutex<JobQueue>, sync::Condvar)) {
+    let (ref lock, ref cvar) = *queue;
+    lock.lock().unwrap().outstanding -= 1;
+    cvar.notify_all();
+}
+
+fn return_job(queue: &(sync::Mutex<JobQueue>, sync::Condvar), tile: grid::GridTile) {
+    let (ref lock, ref cvar) = *queue;
+    lock.lock().unwrap().pending.push_back(tile);
+    cvar.notify_all();
+}
+
+fn abort_jobs(queue: &(sync::Mutex<JobQueue>, sync::Condvar)) {
+    let (ref lock, ref cvar) = *queue;
+    lock.lock().unwrap().aborted = true;
+    cvar.notify_all();
+}
+
+impl WorkerConnection {
+    fn open(addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<Self> {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(addr, timeout)?,
+            None => TcpStream::connect(addr)?,
+        };
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        stream.set_nodelay(true)?;
+
+        let mut connection = WorkerConnection{
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream)};
+        connection.writer.write_all(PROTOCOL_MAGIC)?;
+        encoding::write_u64(&mut connection.writer, PROTOCOL_VERSION)?;
+        connection.writer.flush()?;
+        Ok(connection)
+    }
+
+    fn run_job(&mut self, params: &str, grid: &grid::Grid, tile: &grid::GridTile)
+            -> io::Result<JobResponse> {
+        encoding::write_u8(&mut self.writer, MSG_JOB)?;
+        encoding::write_string(&mut self.writer, params)?;
+        encoding::write_grid(&mut self.writer, grid)?;
+        encoding::write_tile(&mut self.writer, tile)?;
+        self.writer.flush()?;
+
+        match encoding::read_u8(&mut self.reader)? {
+            MSG_RESULT => Ok(JobResponse::Completed(encoding::read_orbits(
+                &mut self.reader, grid.num_samples_in_tile(tile))?)),
+            MSG_ERROR => Ok(JobResponse::Failed(encoding::read_string(&mut self.reader)?)),
+            _ => Err(encoding::invalid_data("unexpected message from worker")),
+        }
+    }
+}
+
+pub fn serve<F>(listener: TcpListener) -> io::Result<()>
+        where F: Fractal + FromParameters + Send + Sync + 'static {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || serve_connection::<F>(stream));
+    }
+    Ok(())
+}
+
+pub fn serve_connection<F>(stream: TcpStream) -> io::Result<()>
+        where F: Fractal + FromParameters + Send + Sync {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = vec![0; PROTOCOL_MAGIC.len()];
+    io::Read::read_exact(&mut reader, &mut magic)?;
+    if magic != PROTOCOL_MAGIC || encoding::read_u64(&mut reader)? != PROTOCOL_VERSION {
+        return Err(encoding::invalid_data("unsupported client protocol"));
+    }
+
+    let mut runner: Option<(String, RayonRunner<F>)> = None;
+    loop {
+        let msg = match encoding::read_u8(&mut reader) {
+            Ok(msg) => msg,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if msg != MSG_JOB {
+            return Err(encoding::invalid_data("unexpected message from client"));
+        }
+
+        let params = encoding::read_string(&mut reader)?;
+        let grid = encoding::read_grid(&mut reader)?;
+        let tile = encoding::read_tile(&mut reader)?;
+
+        let same_fractal = match runner {
+            Some((ref current, _)) => *current == params,
+            None => false,
+        };
+        if !same_fractal {
+            runner = F::from_parameters(&params)
+                .map(|fractal| (params.clone(), RayonRunner::new(fractal)));
+        }
+
+        let result = match runner {
+            Some((_, ref runner)) if tile_fits(&grid, &tile) =>
+                runner.run(&grid.tile_grid(&tile)).map_err(|e| e.to_string()),
+            Some(_) => Err(format!("{} is outside the grid", tile)),
+            None => Err(format!("Unknown fractal parameters '{}'", params)),
+        };
+
+        match result {
+            Ok(values) => {
+                encoding::write_u8(&mut writer, MSG_RESULT)?;
+                encoding::write_orbits(&mut writer, &values)?;
+            },
+            Err(msg) => {
+                encoding::write_u8(&mut writer, MSG_ERROR)?;
+                encoding::write_string(&mut writer, &msg)?;
+            }
+        }
+        writer.flush()?;
+    }
+}
+
+fn tile_fits(grid: &grid::Grid, tile: &grid::GridTile) -> bool {
+    tile.width > 0 && tile.height > 0
+        && tile.x.checked_add(tile.width).is_some_and(|end| end <= grid.cells_wide())
+        && tile.y.checked_add(tile.height).is_some_and(|end| end <= grid.cells_high())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Shutdown;
+    use std::time::Instant;
+
+    use num_complex::Complex;
+    use runner::SyncronousRunner;
+    use super::*;
+
+    // Slow enough that a run takes a while, so a worker can be killed while
+    // it still has jobs. Samples take turns, so the run takes as long on any
+    // number of cores.
+    static SAMPLE_LOCK: sync::Mutex<()> = sync::Mutex::new(());
+
+    #[derive(Clone)]
+    struct SlowFractal;
+
+    impl Fractal for SlowFractal {
+        fn test(&self, c: Complex<f64>) -> FractalOrbit {
+            let _turn = SAMPLE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            thread::sleep(Duration::from_micros(200));
+            FractalOrbit::Escaped(c.re * 1000.0 + c.im)
+        }
+    }
+
+    impl FractalParameters for SlowFractal {
+        fn parameters(&self) -> String {
+            "slow".to_string()
+        }
+    }
+
+    impl FromParameters for SlowFractal {
+        fn from_parameters(params: &str) -> Option<Self> {
+            if params == "slow" { Some(SlowFractal) } else { None }
+        }
+    }
+
+    fn start_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve::<SlowFractal>(listener));
+        addr
+    }
+
+    // A worker whose only connection is cut off `after` it's been accepted.
+    fn start_doomed_worker(after: Duration) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let connection = stream.try_clone().unwrap();
+            thread::spawn(move || serve_connection::<SlowFractal>(connection));
+            thread::sleep(after);
+            stream.shutdown(Shutdown::Both).unwrap();
+        });
+        addr
+    }
+
+    // A worker that accepts a connection and never answers.
+    fn start_hung_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(600));
+        });
+        addr
+    }
+
+    fn grid() -> grid::Grid {
+        grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 48, 32)
+    }
+
+    #[test]
+    fn killed_worker_jobs_are_reassigned() {
+        let workers = vec![start_worker(), start_doomed_worker(Duration::from_millis(30)),
+                           start_worker()];
+        let mut runner = NetworkRunner::new(&SlowFractal, workers);
+        runner.set_tile_size(8, 8);
+
+        let values = runner.run(&grid()).unwrap();
+        assert!(values == SyncronousRunner::new(SlowFractal).run(&grid()).unwrap());
+    }
+
+    #[test]
+    fn hung_worker_times_out() {
+        assert_eq!(NetworkRunner::new(&SlowFractal, Vec::new()).timeout(),
+                   Some(Duration::from_secs(DEFAULT_TIMEOUT_SECS)));
+
+        let mut runner = NetworkRunner::new(&SlowFractal, vec![start_hung_worker(),
+                                                               start_worker()]);
+        runner.set_tile_size(16, 16);
+        runner.set_timeout(Some(Duration::from_millis(200)));
+
+        let start = Instant::now();
+        let values = runner.run(&grid()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert!(values == SyncronousRunner::new(SlowFractal).run(&grid()).unwrap());
+    }
+
+    #[test]
+    fn losing_every_worker_is_an_error() {
+        let mut runner = NetworkRunner::new(&SlowFractal,
+                                            vec![start_doomed_worker(Duration::from_millis(10))]);
+        runner.set_tile_size(8, 8);
+        assert!(runner.run(&grid()).is_err());
+    }
+}
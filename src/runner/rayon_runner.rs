@@ -0,0 +1,171 @@
+use std::sync;
+
+use rayon;
+use rayon::prelude::*;
+
+use num_complex::{Complex};
+use fractal::{Fractal, FractalOrbit, FractalParameters};
+use grid;
+use runner::{FractalRunner, RunnerError, RunnerResult, DEFAULT_TILE_SIZE};
+
+// Runs a fractal on a rayon thread pool. Without an explicit pool the global
+// rayon pool is used, which is sized to the number of cores and is shared with
+// every other rayon-parallel stage (such as the mappers) automatically. A
+// runner that owns a pool shares it by running those stages through `install`.
+pub struct RayonRunner<T> {
+    fractal: T,
+    pool: Option<sync::Arc<rayon::ThreadPool>>,
+    tile_size: (usize, usize),
+}
+
+impl<T: Fractal + Send + Sync> RayonRunner<T> {
+    pub fn new(fractal: T) -> Self {
+        RayonRunner{fractal: fractal, pool: None,
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE)}
+    }
+
+    pub fn with_threads(fractal: T, num_threads: usize) -> RunnerResult<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .map_err(|e| RunnerError::RunnerError(e.to_string()))?;
+
+        Ok(RayonRunner::with_pool(fractal, sync::Arc::new(pool)))
+    }
+
+    pub fn with_pool(fractal: T, pool: sync::Arc<rayon::ThreadPool>) -> Self {
+        RayonRunner{fractal: fractal, pool: Some(pool),
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE)}
+    }
+
+    pub fn set_tile_size(&mut self, width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            panic!("Tile size must be at least 1x1");
+        }
+        self.tile_size = (width, height);
+    }
+
+    pub fn pool(&self) -> Option<&sync::Arc<rayon::ThreadPool>> {
+        self.pool.as_ref()
+    }
+
+    pub fn num_threads(&self) -> usize {
+        match self.pool {
+            Some(ref pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        }
+    }
+
+    pub fn install<R, F>(&self, op: F) -> R where R: Send, F: FnOnce() -> R + Send {
+        match self.pool {
+            Some(ref pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
+
+impl<T: Fractal + FractalParameters> FractalParameters for RayonRunner<T> {
+    fn parameters(&self) -> String {
+        self.fractal.parameters()
+    }
+}
+
+impl<T: Fractal + Send + Sync> FractalRunner for RayonRunner<T> {
+    fn run(&self, grid: &grid::Grid) -> RunnerResult<Vec<FractalOrbit>> {
+        let mut values = vec![FractalOrbit::Bounded; grid.num_samples()];
+        if values.is_empty() {
+            return Ok(values);
+        }
+
+        let (tile_width, tile_height) = self.tile_size;
+        let fractal = &self.fractal;
+        {
+            let tiles = grid.split_tiles(tile_width, tile_height, &mut values);
+            self.install(|| {
+                tiles.into_par_iter().for_each(|(tile, mut output)| {
+                    let cells = output.iter_mut().flat_map(|row| row.iter_mut());
+                    for (cell, (_, (x, y))) in cells.zip(tile.iter_samples(grid)) {
+                        *cell = fractal.test(Complex::new(x, y));
+                    }
+                });
+            });
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    // Pool sizes and worker indices.
+    type Workers = HashSet<(usize, Option<usize>)>;
+
+    // Records the size of the pool and the worker index each point ran on.
+    #[derive(Clone)]
+    struct PoolFractal {
+        seen: sync::Arc<sync::Mutex<Workers>>,
+    }
+
+    impl PoolFractal {
+        fn new() -> Self {
+            PoolFractal{seen: sync::Arc::new(sync::Mutex::new(HashSet::new()))}
+        }
+    }
+
+    impl Fractal for PoolFractal {
+        fn test(&self, _c: Complex<f64>) -> FractalOrbit {
+            let worker = (rayon::current_num_threads(), rayon::current_thread_index());
+            self.seen.lock().unwrap().insert(worker);
+            FractalOrbit::Bounded
+        }
+    }
+
+    #[test]
+    fn uses_the_global_pool_by_default() {
+        let runner = RayonRunner::new(PoolFractal::new());
+        assert!(runner.pool().is_none());
+        assert_eq!(runner.num_threads(), rayon::current_num_threads());
+    }
+
+    #[test]
+    fn thread_count_overrides_run_on_their_own_pool() {
+        let fractal = PoolFractal::new();
+        let mut runner = RayonRunner::with_threads(fractal.clone(), 3).unwrap();
+        runner.set_tile_size(4, 4);
+        assert_eq!(runner.num_threads(), 3);
+
+        let grid = grid::Grid::new(-2.0, 1.0, 1.0, -1.0, 40, 30);
+        assert_eq!(runner.run(&grid).unwrap().len(), 40 * 30);
+        let seen = fractal.seen.lock().unwrap();
+        assert!(!seen.is_empty());
+        for &(num_threads, index) in seen.iter() {
+            assert_eq!(num_threads, 3);
+            assert!(index.is_some_and(|index| index < 3));
+        }
+    }
+
+    #[test]
+    fn runners_share_a_pool_with_other_stages() {
+        let pool = sync::Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+        let first = RayonRunner::with_pool(PoolFractal::new(), pool.clone());
+        let second = RayonRunner::with_pool(PoolFractal::new(), pool.clone());
+        assert!(sync::Arc::ptr_eq(first.pool().unwrap(), second.pool().unwrap()));
+        assert_eq!(sync::Arc::strong_count(&pool), 3);
+
+        // Parallel stages run through `install` land on the same workers.
+        let workers: HashSet<_> = first.install(|| {
+            (0..64).into_par_iter().map(|_| rayon::current_num_threads()).collect()
+        });
+        assert_eq!(workers, [2].iter().cloned().collect());
+    }
+
+    #[test]
+    #[should_panic(expected = "Tile size")]
+    fn empty_tiles_are_rejected() {
+        RayonRunner::new(PoolFractal::new()).set_tile_size(0, 8);
+    }
+}
@@ -0,0 +1,161 @@
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SamplePattern {
+    #[default]
+    Center,
+    Regular(usize),
+    Jittered(usize),
+    Stratified(usize),
+    RotatedGrid,
+    Halton(usize),
+}
+
+// Sub-pixel offsets of the rotated grid supersampling pattern.
+const ROTATED_GRID: [(f64, f64); 4] =
+    [(0.375, 0.125), (0.875, 0.375), (0.625, 0.875), (0.125, 0.625)];
+
+impl SamplePattern {
+    pub fn samples_per_cell(&self) -> usize {
+        match *self {
+            SamplePattern::Center => 1,
+            SamplePattern::Regular(n) => n*n,
+            SamplePattern::Jittered(n) => n,
+            SamplePattern::Stratified(n) => n*n,
+            SamplePattern::RotatedGrid => ROTATED_GRID.len(),
+            SamplePattern::Halton(n) => n,
+        }
+    }
+
+    // Offsets of each sample within the cell at lattice column `x` and row
+    // `y`, with (0, 0) and (1, 1) at opposite corners of the cell. Random
+    // patterns are seeded by the cell, so the same lattice cell always gets
+    // the same samples.
+    pub fn offsets(&self, x: i64, y: i64) -> Vec<(f64, f64)> {
+        let seed = cell_seed(x, y);
+        match *self {
+            SamplePattern::Center => vec![(0.5, 0.5)],
+            SamplePattern::Regular(n) => grid_offsets(n, |_| (0.5, 0.5)),
+            SamplePattern::Jittered(n) => (0..n)
+                .map(|i| sample_random(seed, i))
+                .collect(),
+            SamplePattern::Stratified(n) => grid_offsets(n, |i| sample_random(seed, i)),
+            SamplePattern::RotatedGrid => ROTATED_GRID.to_vec(),
+            SamplePattern::Halton(n) => (1..n+1)
+                .map(|i| (radical_inverse(i, 2), radical_inverse(i, 3)))
+                .collect(),
+        }
+    }
+}
+
+fn grid_offsets<F: Fn(usize) -> (f64, f64)>(n: usize, within: F) -> Vec<(f64, f64)> {
+    let step = 1.0 / (n as f64);
+    let mut offsets = Vec::with_capacity(n*n);
+    for j in 0..n {
+        for i in 0..n {
+            let (u, v) = within(j*n + i);
+            offsets.push((((i as f64) + u) * step, ((j as f64) + v) * step));
+        }
+    }
+    offsets
+}
+
+fn radical_inverse(mut index: usize, base: usize) -> f64 {
+    let inv_base = 1.0 / (base as f64);
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += ((index % base) as f64) * scale;
+        index /= base;
+        scale *= inv_base;
+    }
+    result
+}
+
+fn cell_seed(x: i64, y: i64) -> u64 {
+    (x as u64).wrapping_mul(0x9e3779b97f4a7c15) ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f)
+}
+
+fn sample_random(seed: u64, sample: usize) -> (f64, f64) {
+    let seed = seed.wrapping_add(2 * sample as u64);
+    (unit_random(seed), unit_random(seed.wrapping_add(1)))
+}
+
+// A deterministic value in [0, 1) derived from `seed`, so repeated renders of
+// the same grid produce identical images.
+pub fn unit_random(seed: u64) -> f64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / ((1u64 << 53) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns() -> Vec<SamplePattern> {
+        vec![SamplePattern::Center, SamplePattern::Regular(3), SamplePattern::Jittered(5),
+             SamplePattern::Stratified(2), SamplePattern::RotatedGrid,
+             SamplePattern::Halton(7)]
+    }
+
+    fn in_cell(&(u, v): &(f64, f64)) -> bool {
+        (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)
+    }
+
+    #[test]
+    fn offsets_lie_in_the_cell() {
+        for pattern in patterns() {
+            for &(x, y) in [(0, 0), (-3, 7), (1 << 40, -(1 << 40))].iter() {
+                let offsets = pattern.offsets(x, y);
+                assert_eq!(offsets.len(), pattern.samples_per_cell(), "{:?}", pattern);
+                assert!(offsets.iter().all(in_cell), "{:?}: {:?}", pattern, offsets);
+            }
+        }
+        assert_eq!(SamplePattern::default(), SamplePattern::Center);
+    }
+
+    #[test]
+    fn regular_and_fixed_patterns() {
+        assert_eq!(SamplePattern::Center.offsets(4, 2), vec![(0.5, 0.5)]);
+        assert_eq!(SamplePattern::Regular(2).offsets(4, 2),
+                   vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+        assert_eq!(SamplePattern::RotatedGrid.offsets(0, 0),
+                   SamplePattern::RotatedGrid.offsets(9, 9));
+
+        let halton = SamplePattern::Halton(4).offsets(0, 0);
+        let expected = [(0.5, 1.0 / 3.0), (0.25, 2.0 / 3.0),
+                        (0.75, 1.0 / 9.0), (0.125, 4.0 / 9.0)];
+        for (&(u, v), &(eu, ev)) in halton.iter().zip(expected.iter()) {
+            assert!((u - eu).abs() < 1e-12 && (v - ev).abs() < 1e-12, "{:?}", halton);
+        }
+    }
+
+    #[test]
+    fn random_patterns_are_seeded_by_cell() {
+        for &pattern in [SamplePattern::Jittered(4), SamplePattern::Stratified(3)].iter() {
+            assert_eq!(pattern.offsets(5, -2), pattern.offsets(5, -2));
+            assert_ne!(pattern.offsets(5, -2), pattern.offsets(-2, 5));
+            assert_ne!(pattern.offsets(5, -2), pattern.offsets(6, -2));
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_each_stratum() {
+        let n = 4;
+        let offsets = SamplePattern::Stratified(n).offsets(11, 3);
+        for (index, &(u, v)) in offsets.iter().enumerate() {
+            let stratum = ((v * n as f64) as usize, (u * n as f64) as usize);
+            assert_eq!(stratum, (index / n, index % n));
+        }
+    }
+
+    #[test]
+    fn unit_random_stays_in_range() {
+        let values: Vec<f64> = (0..10_000).map(|seed| unit_random(seed * 7919)).collect();
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.5).abs() < 0.02, "mean {}", mean);
+        assert_eq!(unit_random(u64::MAX), unit_random(u64::MAX));
+    }
+}
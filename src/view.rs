@@ -0,0 +1,218 @@
+// Width of the complex plane shown at magnification 1, enough to fit the whole
+// Mandelbrot set.
+pub const UNIT_VIEW_WIDTH: f64 = 4.0;
+
+// A region of the complex plane: a `width` by `height` rectangle around
+// `center`, sheared horizontally by `skew` and then rotated counterclockwise
+// by `rotation` radians about its center.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct View {
+    center: (f64, f64),
+    width: f64,
+    height: f64,
+    rotation: f64,
+    skew: f64,
+}
+
+// Maps lattice coordinates (column, row), with cell (x, y) covering
+// [x, x+1) x [y, y+1), to points in the complex plane.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewTransform {
+    pub origin: (f64, f64),
+    pub column_step: (f64, f64),
+    pub row_step: (f64, f64),
+}
+
+impl View {
+    pub fn new(center: (f64, f64), width: f64, height: f64) -> Self {
+        View{center: center, width: width, height: height, rotation: 0.0, skew: 0.0}
+    }
+
+    // A view `width` wide whose height is chosen so that a `cells_x` by
+    // `cells_y` grid over it has square cells.
+    pub fn with_square_cells(center: (f64, f64), width: f64,
+                             cells_x: usize, cells_y: usize) -> Self {
+        if cells_x == 0 || cells_y == 0 {
+            panic!("View must be at least one cell in each direction");
+        }
+        View::new(center, width, width * (cells_y as f64) / (cells_x as f64))
+    }
+
+    pub fn with_magnification(center: (f64, f64), magnification: f64,
+                              cells_x: usize, cells_y: usize) -> Self {
+        View::with_square_cells(center, UNIT_VIEW_WIDTH / magnification, cells_x, cells_y)
+    }
+
+    pub fn set_rotation(&mut self, rotation: f64) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_skew(&mut self, skew: f64) {
+        self.skew = skew;
+    }
+
+    #[inline]
+    pub fn center(&self) -> (f64, f64) {
+        self.center
+    }
+
+    #[inline]
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    #[inline]
+    pub fn magnification(&self) -> f64 {
+        UNIT_VIEW_WIDTH / self.width
+    }
+
+    #[inline]
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+
+    #[inline]
+    pub fn skew(&self) -> f64 {
+        self.skew
+    }
+
+    // The transform placing a `cells_x` by `cells_y` lattice over this view,
+    // with row 0 at the top.
+    pub fn transform(&self, cells_x: usize, cells_y: usize) -> ViewTransform {
+        let dx = self.width / (cells_x as f64);
+        let dy = -self.height / (cells_y as f64);
+        let (sin, cos) = self.rotation.sin_cos();
+
+        let column_step = (dx * cos, dx * sin);
+        let sheared = self.skew * dy;
+        let row_step = (sheared * cos - dy * sin, sheared * sin + dy * cos);
+
+        let half_x = (cells_x as f64) * 0.5;
+        let half_y = (cells_y as f64) * 0.5;
+        let origin = (self.center.0 - half_x * column_step.0 - half_y * row_step.0,
+                      self.center.1 - half_x * column_step.1 - half_y * row_step.1);
+
+        ViewTransform{origin: origin, column_step: column_step, row_step: row_step}
+    }
+}
+
+impl ViewTransform {
+    // An axis aligned lattice of `dx` by `dy` cells with its corner at `origin`.
+    pub fn axis_aligned(origin: (f64, f64), dx: f64, dy: f64) -> Self {
+        ViewTransform{origin: origin, column_step: (dx, 0.0), row_step: (0.0, dy)}
+    }
+
+    #[inline]
+    pub fn apply(&self, column: f64, row: f64) -> (f64, f64) {
+        (self.origin.0 + column * self.column_step.0 + row * self.row_step.0,
+         self.origin.1 + column * self.column_step.1 + row * self.row_step.1)
+    }
+
+    // Lattice coordinates of the point (x, y), or None if the transform is
+    // degenerate.
+    pub fn invert(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (a, c) = self.column_step;
+        let (b, d) = self.row_step;
+        let det = a * d - b * c;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+
+        let x = x - self.origin.0;
+        let y = y - self.origin.1;
+        Some(((d * x - b * y) / det, (a * y - c * x) / det))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use grid::Grid;
+    use super::*;
+
+    fn assert_near(a: (f64, f64), b: (f64, f64)) {
+        assert!((a.0 - b.0).abs() < 1e-12 && (a.1 - b.1).abs() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    fn tilted() -> View {
+        let mut view = View::new((-0.5, 0.25), 3.0, 2.0);
+        view.set_rotation(0.4);
+        view.set_skew(-0.3);
+        view
+    }
+
+    #[test]
+    fn unrotated_views_are_axis_aligned() {
+        let transform = View::new((1.0, -1.0), 4.0, 2.0).transform(8, 4);
+        assert_eq!(transform, ViewTransform::axis_aligned((-1.0, 0.0), 0.5, -0.5));
+        assert_near(transform.apply(8.0, 4.0), (3.0, -2.0));
+    }
+
+    #[test]
+    fn rotation_turns_the_view_about_its_center() {
+        let mut view = View::new((1.0, 2.0), 4.0, 2.0);
+        view.set_rotation(FRAC_PI_2);
+        let transform = view.transform(4, 2);
+
+        // A quarter turn counterclockwise takes the top left corner to the
+        // bottom left, so columns run upwards and rows to the right.
+        assert_near(transform.apply(2.0, 1.0), (1.0, 2.0));
+        assert_near(transform.apply(0.0, 0.0), (0.0, 0.0));
+        assert_near(transform.apply(4.0, 0.0), (0.0, 4.0));
+        assert_near(transform.column_step, (0.0, 1.0));
+        assert_near(transform.row_step, (1.0, 0.0));
+    }
+
+    #[test]
+    fn skew_shears_rows_horizontally() {
+        let mut view = View::new((0.0, 0.0), 4.0, 2.0);
+        view.set_skew(0.5);
+        let transform = view.transform(4, 2);
+
+        assert_near(transform.apply(2.0, 1.0), (0.0, 0.0));
+        assert_near(transform.column_step, (1.0, 0.0));
+        // Each row down moves a cell lower and half a cell left.
+        assert_near(transform.row_step, (-0.5, -1.0));
+        assert_near(transform.apply(0.0, 0.0), (-1.5, 1.0));
+    }
+
+    #[test]
+    fn invert_undoes_apply() {
+        let transform = tilted().transform(30, 20);
+        for &(column, row) in [(0.0, 0.0), (30.0, 20.0), (12.5, 3.25), (-4.0, 41.0)].iter() {
+            let (x, y) = transform.apply(column, row);
+            assert_near(transform.invert(x, y).unwrap(), (column, row));
+        }
+
+        let flat = ViewTransform{origin: (0.0, 0.0), column_step: (1.0, 2.0),
+                                 row_step: (2.0, 4.0)};
+        assert_eq!(flat.invert(1.0, 1.0), None);
+    }
+
+    #[test]
+    fn grid_cells_follow_the_transform() {
+        let view = tilted();
+        let grid = Grid::from_view(&view, 30, 20);
+        let transform = view.transform(30, 20);
+
+        assert_near(grid.center(), view.center());
+        assert!((grid.width() - 3.0).abs() < 1e-12 && (grid.height() - 2.0).abs() < 1e-12);
+        for &(x, y) in [(0, 0), (29, 0), (7, 13), (29, 19)].iter() {
+            assert_near(grid.cell_position(x, y).unwrap(),
+                        transform.apply(x as f64 + 0.5, y as f64 + 0.5));
+        }
+
+        let sub = grid.subgrid(7, 13, 4, 4);
+        assert_near(sub.cell_position(0, 0).unwrap(), grid.cell_position(7, 13).unwrap());
+        let (left, top, right, bottom) = grid.bounds();
+        for (_, (x, y)) in grid.iter() {
+            assert!(x > left && x < right && y > bottom && y < top);
+        }
+    }
+}